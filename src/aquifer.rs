@@ -0,0 +1,247 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::components::{Aquifer, ElevationBundle, Neighbours};
+use crate::config::Config;
+use crate::terrain::TileType;
+use crate::water_budget::WaterBudget;
+use crate::world::EcosystemAttributes;
+use crate::Epochs;
+
+///////////////////////////////// Aquifers /////////////////////////////////////////
+
+/// How water seeps into the rock beneath the soil and moves through it, set by the `aquifer`
+/// config key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AquiferAttributes {
+    // thickness of the water-bearing rock beneath the soil
+    pub depth: f32,
+    // share of that rock that can hold water
+    pub porosity: f32,
+    // share of a tile's water that seeps down every epoch through the most permeable ground
+    pub infiltration_rate: f32,
+    // share of the difference in water table height that flows to each neighbour every epoch
+    pub conductivity: f32,
+}
+
+impl Default for AquiferAttributes {
+    fn default() -> Self {
+        Config::defaults().aquifer
+    }
+}
+
+impl AquiferAttributes {
+    /// Most water the rock beneath a tile can hold
+    pub fn capacity(&self) -> f32 {
+        self.depth * self.porosity
+    }
+
+    /// Height of the water table beneath a tile.
+    ///
+    /// It lies within the rock until the rock is full, after which the water soaked into the
+    /// soil above carries it on up to the surface. The ocean's table is the sea surface.
+    pub fn water_table(
+        &self,
+        elevation: &ElevationBundle,
+        aquifer: &Aquifer,
+        tile_type: TileType,
+    ) -> f32 {
+        if tile_type == TileType::Ocean {
+            return elevation.bedrock.value + elevation.soil.value + elevation.water.value;
+        }
+        if aquifer.water < self.capacity() {
+            elevation.bedrock.value - self.depth + aquifer.water / self.porosity
+        } else {
+            elevation.bedrock.value + elevation.water.value.min(elevation.soil.value)
+        }
+    }
+}
+
+/// Seeps water down into the rock, moves it towards neighbours with a lower water table, and
+/// pushes whatever a full aquifer can't hold back up to the surface as a spring. Groundwater
+/// reaching the coast flows out into the ocean.
+pub fn groundwater_flow_system(
+    mut debug: ResMut<Epochs>,
+    mut query: Query<(
+        Entity,
+        &mut Aquifer,
+        &mut ElevationBundle,
+        &TileType,
+        &Neighbours,
+    )>,
+    ecosystem_attributes: Res<EcosystemAttributes>,
+    mut budget: ResMut<WaterBudget>,
+) {
+    debug.fn_order.push("groundwater_flow_system".to_string());
+    let attributes = &ecosystem_attributes.aquifer;
+    let capacity = attributes.capacity();
+
+    // seep down first, so that the flows below see this epoch's water tables
+    let mut water_tables: HashMap<Entity, (f32, TileType)> = HashMap::new();
+    for (entity, mut aquifer, mut elevation, tile_type, _) in query.iter_mut() {
+        aquifer.infiltration = match tile_type {
+            TileType::Ocean => 0.0,
+            _ => (attributes.infiltration_rate * tile_type.permeability() * elevation.water.value)
+                .min(capacity - aquifer.water)
+                .max(0.0),
+        };
+        aquifer.water += aquifer.infiltration;
+        elevation.water.value -= aquifer.infiltration;
+        budget.infiltration += aquifer.infiltration;
+
+        water_tables.insert(
+            entity,
+            (
+                attributes.water_table(&elevation, &aquifer, *tile_type),
+                *tile_type,
+            ),
+        );
+    }
+
+    // every tile sends some of its water towards each neighbour whose table is lower, all of
+    // them worked out from the same water tables so the order tiles are visited in doesn't matter
+    let mut inflow: HashMap<Entity, f32> = HashMap::new();
+    let mut outflow: HashMap<Entity, f32> = HashMap::new();
+    for (entity, aquifer, _, tile_type, neighbours) in query.iter() {
+        if *tile_type == TileType::Ocean || aquifer.water <= 0.0 {
+            continue;
+        }
+        let (water_table, _) = water_tables[&entity];
+        // water that flows per unit the neighbour's table lies lower
+        let flow_per_drop =
+            attributes.conductivity * attributes.porosity / neighbours.ids.len() as f32;
+        let flows: Vec<(Entity, f32, TileType)> = neighbours
+            .ids
+            .iter()
+            .filter_map(|neighbour| {
+                let (neighbour_table, neighbour_type) = water_tables.get(neighbour)?;
+                (*neighbour_table < water_table).then(|| {
+                    (
+                        *neighbour,
+                        flow_per_drop * (water_table - neighbour_table),
+                        *neighbour_type,
+                    )
+                })
+            })
+            .collect();
+
+        // an aquifer can't send more than it holds
+        let total: f32 = flows.iter().map(|(_, flow, _)| flow).sum();
+        let scale = match total > aquifer.water {
+            true => aquifer.water / total,
+            false => 1.0,
+        };
+        for (neighbour, flow, neighbour_type) in flows {
+            let flow = flow * scale;
+            match neighbour_type {
+                TileType::Ocean => {
+                    budget.ocean_received(flow);
+                    budget.runoff_to_ocean += flow;
+                }
+                _ => *inflow.entry(neighbour).or_default() += flow,
+            }
+        }
+        outflow.insert(entity, total * scale);
+    }
+
+    // whatever a full aquifer can't hold rises to the surface
+    for (entity, mut aquifer, mut elevation, _, _) in query.iter_mut() {
+        aquifer.water += inflow.get(&entity).copied().unwrap_or(0.0)
+            - outflow.get(&entity).copied().unwrap_or(0.0);
+        aquifer.spring = (aquifer.water - capacity).max(0.0);
+        aquifer.water = aquifer.water.clamp(0.0, capacity);
+        elevation.water.value += aquifer.spring;
+        budget.spring_discharge += aquifer.spring;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    /// Grass tiles in a row, each on 1 of soil and given as its bedrock height, the water
+    /// soaked into its soil and the water in its aquifer
+    fn row(world: &mut World, tiles: &[(f32, f32, f32)]) -> Vec<Entity> {
+        let entities: Vec<Entity> = tiles
+            .iter()
+            .map(|&(bedrock, water, aquifer)| {
+                let mut elevation = ElevationBundle::from(TileType::Grass, bedrock, 0.0);
+                elevation.soil.value = 1.0;
+                elevation.water.value = water;
+                world
+                    .spawn((
+                        Aquifer {
+                            water: aquifer,
+                            ..default()
+                        },
+                        elevation,
+                        TileType::Grass,
+                    ))
+                    .id()
+            })
+            .collect();
+        for (index, &entity) in entities.iter().enumerate() {
+            let ids = [index.checked_sub(1), Some(index + 1)]
+                .into_iter()
+                .flatten()
+                .filter_map(|neighbour| entities.get(neighbour).copied())
+                .collect();
+            world.entity_mut(entity).insert(Neighbours { ids });
+        }
+        world.insert_resource(Epochs::default());
+        world.insert_resource(EcosystemAttributes::from(&Config::defaults()));
+        world.insert_resource(WaterBudget::default());
+        entities
+    }
+
+    fn water(world: &World, tile: Entity) -> f32 {
+        world.get::<Aquifer>(tile).unwrap().water
+            + world.get::<ElevationBundle>(tile).unwrap().water.value
+    }
+
+    #[test]
+    fn full_aquifer_overflows_as_a_spring() {
+        let capacity = AquiferAttributes::default().capacity();
+        let mut world = World::new();
+        // groundwater runs down from the soaked hillside into the valley, which is already full
+        let tiles = row(&mut world, &[(5.0, 1.0, capacity), (1.0, 0.0, capacity)]);
+        world.run_system_once(groundwater_flow_system);
+
+        let valley = world.get::<Aquifer>(tiles[1]).unwrap();
+        assert!(valley.spring > 0.0);
+        assert_eq!(valley.water, capacity);
+        assert_eq!(
+            world.get::<ElevationBundle>(tiles[1]).unwrap().water.value,
+            valley.spring
+        );
+        assert_eq!(
+            world.resource::<WaterBudget>().spring_discharge,
+            valley.spring
+        );
+    }
+
+    #[test]
+    fn groundwater_flow_conserves_water() {
+        let capacity = AquiferAttributes::default().capacity();
+        let mut world = World::new();
+        let tiles = row(
+            &mut world,
+            &[
+                (4.0, 0.8, capacity),
+                (3.0, 0.5, capacity / 2.0),
+                (1.0, 0.0, capacity),
+                (2.0, 0.2, 0.0),
+            ],
+        );
+        let total = |world: &World| tiles.iter().map(|&tile| water(world, tile)).sum::<f32>();
+        let before = total(&world);
+        for _ in 0..10 {
+            world.run_system_once(groundwater_flow_system);
+        }
+        assert!((total(&world) - before).abs() < 1e-5);
+        assert!(world.resource::<WaterBudget>().spring_discharge > 0.0);
+    }
+}
@@ -0,0 +1,113 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::config::Config;
+use crate::terrain::TileType;
+use crate::world::TemperatureAttributes;
+use crate::Epochs;
+
+// greenhouse gas pressures the energy balance is tuned for, in kPa, which cause no extra forcing
+pub const REFERENCE_CO2: f32 = 0.04;
+pub const REFERENCE_CH4: f32 = 0.00018;
+
+// pressures are floored at a trace so the forcing of a gas that has run out stays finite
+const TRACE_PRESSURE: f32 = 1e-6;
+
+///////////////////////////////// Atmosphere /////////////////////////////////////////
+
+/// The gases making up the planet's air, as partial pressures in kPa
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Resource)]
+#[serde(deny_unknown_fields)]
+pub struct Atmosphere {
+    pub co2: f32,
+    pub o2: f32,
+    pub n2: f32,
+    pub ch4: f32,
+}
+
+impl Atmosphere {
+    /// Total surface pressure in kPa
+    pub fn pressure(&self) -> f32 {
+        self.co2 + self.o2 + self.n2 + self.ch4
+    }
+
+    /// Extra heating (W/m^2) from the greenhouse gases compared to the reference pressures,
+    /// growing by a fixed amount every time a gas doubles
+    pub fn greenhouse_forcing(&self, attributes: &AtmosphereAttributes) -> f32 {
+        let doublings =
+            |pressure: f32, reference: f32| (pressure.max(TRACE_PRESSURE) / reference).log2();
+        attributes.co2_forcing * doublings(self.co2, REFERENCE_CO2)
+            + attributes.ch4_forcing * doublings(self.ch4, REFERENCE_CH4)
+    }
+}
+
+/// Composition the planet starts with and how its gases are made and used up, set by the
+/// `atmosphere` config key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AtmosphereAttributes {
+    pub composition: Atmosphere,
+    // W/m^2 of extra heating every time the gas doubles
+    pub co2_forcing: f32,
+    pub ch4_forcing: f32,
+    // kPa of CO2 released for every unit the volcanoes raise the bedrock
+    pub volcanic_outgassing: f32,
+    // share of the CO2 turned into O2 every epoch, if the whole planet were forest or jungle
+    pub photosynthesis: f32,
+    // share of the CO2 dissolved every epoch, if the whole planet were ocean
+    pub ocean_uptake: f32,
+}
+
+impl Default for AtmosphereAttributes {
+    fn default() -> Self {
+        Config::defaults().atmosphere
+    }
+}
+
+/// Forests and jungles turn CO2 into O2 and oceans dissolve CO2, in proportion to how much of
+/// the planet they cover. Volcanoes add theirs as they erupt.
+pub fn carbon_cycle_system(
+    mut debug: ResMut<Epochs>,
+    query: Query<&TileType>,
+    temperature_attributes: Res<TemperatureAttributes>,
+    mut atmosphere: ResMut<Atmosphere>,
+) {
+    debug.fn_order.push("carbon_cycle_system".to_string());
+    let attributes = &temperature_attributes.atmosphere;
+
+    let mut tiles = 0;
+    let mut forest = 0;
+    let mut ocean = 0;
+    for tile_type in query.iter() {
+        tiles += 1;
+        match tile_type {
+            TileType::Forest | TileType::Jungle => forest += 1,
+            TileType::Ocean => ocean += 1,
+            _ => (),
+        }
+    }
+    if tiles == 0 {
+        return;
+    }
+
+    let photosynthesised =
+        atmosphere.co2 * attributes.photosynthesis * forest as f32 / tiles as f32;
+    let dissolved = atmosphere.co2 * attributes.ocean_uptake * ocean as f32 / tiles as f32;
+    atmosphere.co2 = (atmosphere.co2 - photosynthesised - dissolved).max(0.0);
+    atmosphere.o2 += photosynthesised;
+}
+
+impl fmt::Display for Atmosphere {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Atmosphere: {:.2} kPa (CO2 {:.4}, O2 {:.2}, N2 {:.2}, CH4 {:.5})",
+            self.pressure(),
+            self.co2,
+            self.o2,
+            self.n2,
+            self.ch4
+        )
+    }
+}
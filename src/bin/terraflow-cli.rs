@@ -0,0 +1,120 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use clap::Parser;
+
+use terraflow::config::CONFIG_ENV_VAR;
+use terraflow::rules::RULES_ENV_VAR;
+use terraflow::save::SaveFile;
+use terraflow::statistics::EpochStatistics;
+use terraflow::utils::SEED_ENV_VAR;
+use terraflow::world::WorldAttributes;
+use terraflow::World;
+
+/// Generates a world and runs it headlessly for a number of epochs
+#[derive(Debug, Parser)]
+#[command(name = "terraflow-cli", version)]
+struct Args {
+    /// World config file layered over the built-in defaults
+    #[arg(long, env = CONFIG_ENV_VAR)]
+    config: Option<PathBuf>,
+
+    /// Terrain transition rules file used instead of the built-in rules
+    #[arg(long, env = RULES_ENV_VAR)]
+    rules: Option<PathBuf>,
+
+    /// Seed for world generation and the simulation, random when omitted
+    #[arg(long, env = SEED_ENV_VAR)]
+    seed: Option<u64>,
+
+    /// Continue a saved world instead of generating one
+    #[arg(long, conflicts_with_all = ["config", "rules", "seed"])]
+    load: Option<PathBuf>,
+
+    /// Number of epochs to run
+    #[arg(long, default_value_t = 100)]
+    epochs: u32,
+
+    /// Where to save the final world state, which can be loaded again
+    #[arg(long, default_value = "final_state.json")]
+    output: PathBuf,
+
+    /// Where to write the per-epoch statistics (CSV)
+    #[arg(long, default_value = "statistics.csv")]
+    stats: PathBuf,
+
+    /// Print progress every this many epochs, 0 to disable
+    #[arg(long, default_value_t = 100)]
+    progress_every: u32,
+}
+
+fn main() {
+    if let Err(error) = run(Args::parse()) {
+        eprintln!("Error: {}", error);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    let mut world = match &args.load {
+        Some(path) => {
+            let save = SaveFile::read(path)?;
+            println!(
+                "Loaded world at epoch {} (seed {})",
+                save.epochs,
+                save.rng.seed()
+            );
+            World::from_save(save)
+        }
+        None => {
+            let attributes = match &args.config {
+                Some(path) => WorldAttributes::from_path(path)?,
+                None => WorldAttributes::load(),
+            };
+            let attributes = match &args.rules {
+                Some(path) => attributes.with_rules(path)?,
+                None => attributes,
+            };
+            let seed = args.seed.unwrap_or_else(rand::random);
+            println!("World seed: {}", seed);
+            World::new(attributes, seed)
+        }
+    };
+    println!("Set up world in {:?}", start.elapsed());
+
+    let mut stats = BufWriter::new(File::create(&args.stats)?);
+    writeln!(stats, "{}", EpochStatistics::csv_header())?;
+    writeln!(stats, "{}", world.statistics().csv_row())?;
+
+    let start = Instant::now();
+    let target = world.epochs() + args.epochs;
+    for _ in 0..args.epochs {
+        world.step();
+        writeln!(stats, "{}", world.statistics().csv_row())?;
+
+        if args.progress_every > 0 && world.epochs() % args.progress_every == 0 {
+            println!(
+                "Epoch {}/{} ({:?} elapsed)",
+                world.epochs(),
+                target,
+                start.elapsed()
+            );
+        }
+    }
+    stats.flush()?;
+
+    world.save().write(&args.output)?;
+
+    println!(
+        "Ran {} epochs in {:?}, wrote {} and {}",
+        args.epochs,
+        start.elapsed(),
+        args.output.display(),
+        args.stats.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::terrain::TileType;
+use crate::utils::RandomSelection;
+use crate::world::ElevationAttributes;
+
+////////////////////////////////////////// Biome Table /////////////////////////////////////////
+
+/// Whittaker-style lookup from a tile's climate and height to the tile types it can start as.
+///
+/// Biomes are checked in order and the first one whose bands all match is used, so the last
+/// biome has no bands and catches everything else.
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
+#[serde(transparent)]
+pub struct BiomeTable {
+    pub biomes: Vec<Biome>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Biome {
+    // degrees
+    #[serde(default, skip_serializing_if = "Band::is_unbounded")]
+    pub temperature: Band,
+    // 0 - 1
+    #[serde(default, skip_serializing_if = "Band::is_unbounded")]
+    pub humidity: Band,
+    // fraction of highest_elevation
+    #[serde(default, skip_serializing_if = "Band::is_unbounded")]
+    pub altitude: Band,
+    // only match tiles on one side of sea level
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub below_sea_level: Option<bool>,
+    // tile types and their relative weights
+    pub tiles: Vec<(TileType, f32)>,
+}
+
+/// Inclusive range, open ended on either side that is left out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Band {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f32>,
+}
+
+impl Band {
+    pub fn contains(&self, value: f32) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.min.is_none() && self.max.is_none()
+    }
+
+    /// Same band with both ends multiplied by `factor`
+    fn scaled(&self, factor: f32) -> Band {
+        Band {
+            min: self.min.map(|min| min * factor),
+            max: self.max.map(|max| max * factor),
+        }
+    }
+}
+
+impl Biome {
+    pub fn matches(
+        &self,
+        temperature: f32,
+        humidity: f32,
+        altitude: f32,
+        elevation_attributes: &ElevationAttributes,
+    ) -> bool {
+        let below_sea_level = altitude <= elevation_attributes.sea_level;
+        self.temperature.contains(temperature)
+            && self.humidity.contains(humidity)
+            && self
+                .altitude
+                .scaled(elevation_attributes.highest_elevation)
+                .contains(altitude)
+            && self
+                .below_sea_level
+                .is_none_or(|below| below == below_sea_level)
+    }
+
+    /// Catches every tile, whatever its climate
+    pub fn is_catch_all(&self) -> bool {
+        self.temperature.is_unbounded()
+            && self.humidity.is_unbounded()
+            && self.altitude.is_unbounded()
+            && self.below_sea_level.is_none()
+    }
+}
+
+impl Default for BiomeTable {
+    fn default() -> Self {
+        Config::defaults().biomes
+    }
+}
+
+impl BiomeTable {
+    /// First biome that matches the given conditions
+    pub fn biome(
+        &self,
+        temperature: f32,
+        humidity: f32,
+        altitude: f32,
+        elevation_attributes: &ElevationAttributes,
+    ) -> Option<&Biome> {
+        self.biomes
+            .iter()
+            .find(|biome| biome.matches(temperature, humidity, altitude, elevation_attributes))
+    }
+
+    /// Picks the starting tile type for a freshly generated tile
+    pub fn spawn_tile<R: Rng + ?Sized>(
+        &self,
+        temperature: f32,
+        humidity: f32,
+        altitude: f32,
+        elevation_attributes: &ElevationAttributes,
+        rng: &mut R,
+    ) -> TileType {
+        let biome = self
+            .biome(temperature, humidity, altitude, elevation_attributes)
+            .expect("biome table has no catch-all biome");
+
+        // no need to draw when there is nothing to choose between
+        match biome.tiles.as_slice() {
+            [(tile_type, _)] => *tile_type,
+            _ => biome.tiles.pick_random(rng),
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::atmosphere::Atmosphere;
+use crate::components::{
+    BedrockElevation, ElevationBundle, GeoCoordinates, HexCoordinates, Neighbours, Snowpack,
+    Temperature, Wind,
+};
+use crate::grid::Grid;
+use crate::terrain::TileType;
+use crate::world::{EcosystemAttributes, TemperatureAttributes, WindAttributes};
+use crate::Epochs;
+
+///////////////////////////////// Seasons /////////////////////////////////////////
+
+/// The planet's path around its sun, which sets the seasons
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Orbit {
+    // degrees
+    pub axial_tilt: f32,
+    // epochs
+    pub year_length: u32,
+    pub eccentricity: f32,
+}
+
+/// Where the world is in its year, updated at the start of every epoch
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct Season {
+    // 0 at the start of the year, which is the spring equinox and the closest approach to the sun
+    pub year_fraction: f32,
+    // latitude where the sun is overhead at noon
+    pub sub_solar_latitude: f32,
+    // sunlight compared to a circular orbit, from the distance to the sun
+    pub distance_factor: f32,
+}
+
+impl Season {
+    pub fn at(orbit: &Orbit, epoch: u32) -> Self {
+        let year_fraction = (epoch % orbit.year_length.max(1)) as f32 / orbit.year_length as f32;
+        let angle = year_fraction * std::f32::consts::TAU;
+        let eccentricity = orbit.eccentricity;
+        Season {
+            year_fraction,
+            sub_solar_latitude: orbit.axial_tilt * angle.sin(),
+            distance_factor: ((1.0 + eccentricity * angle.cos())
+                / (1.0 - eccentricity * eccentricity))
+                .powi(2),
+        }
+    }
+
+    /// No tilt and a circular orbit, which gives the yearly mean
+    pub fn mean() -> Self {
+        Season {
+            year_fraction: 0.0,
+            sub_solar_latitude: 0.0,
+            distance_factor: 1.0,
+        }
+    }
+}
+
+pub fn season_system(
+    mut debug: ResMut<Epochs>,
+    mut season: ResMut<Season>,
+    temperature_attributes: Res<TemperatureAttributes>,
+) {
+    debug.fn_order.push("season_system".to_string());
+    *season = Season::at(&temperature_attributes.orbit, debug.epochs);
+}
+
+///////////////////////////////// Energy Balance /////////////////////////////////////////
+
+// A Budyko-style energy balance: every tile absorbs sunlight according to its latitude and
+// albedo, radiates heat away linearly with its temperature and trades heat with its
+// neighbours. Oceans store more heat, so they warm and cool more slowly than land.
+
+/// Sunlight reaching a latitude, in W/m^2, with the warmest band following the overhead sun
+pub fn insolation(
+    temperature_attributes: &TemperatureAttributes,
+    season: &Season,
+    latitude: f32,
+) -> f32 {
+    let energy_balance = &temperature_attributes.energy_balance;
+    // second Legendre polynomial of the sine of the angle away from the overhead sun
+    let x = (latitude - season.sub_solar_latitude)
+        .clamp(-90.0, 90.0)
+        .to_radians()
+        .sin();
+    let p2 = (3.0 * x * x - 1.0) / 2.0;
+    energy_balance.solar_constant
+        * (1.0 - energy_balance.insolation_variation * p2)
+        * season.distance_factor
+}
+
+/// Sunlight at a latitude compared to its yearly mean
+pub fn relative_insolation(
+    temperature_attributes: &TemperatureAttributes,
+    season: &Season,
+    latitude: f32,
+) -> f32 {
+    insolation(temperature_attributes, season, latitude)
+        / insolation(temperature_attributes, &Season::mean(), latitude)
+}
+
+/// Heat gained by a tile this epoch (W/m^2) before it is divided by the tile's heat capacity
+fn net_heating(
+    temperature_attributes: &TemperatureAttributes,
+    season: &Season,
+    greenhouse_forcing: f32,
+    temperature: f32,
+    latitude: f32,
+    bedrock: &BedrockElevation,
+    albedo: f32,
+) -> f32 {
+    let energy_balance = &temperature_attributes.energy_balance;
+    let absorbed = insolation(temperature_attributes, season, latitude) * (1.0 - albedo);
+    // higher ground radiates as if it were warmer than it is, so it settles colder
+    let lapse = bedrock.value.max(0.0) * temperature_attributes.altitude_temperature_variation;
+    // greenhouse gases trap some of the heat that would otherwise escape to space
+    let emitted = energy_balance.outgoing_radiation_base
+        + energy_balance.outgoing_radiation_slope * (temperature + lapse)
+        - greenhouse_forcing;
+    absorbed - emitted
+}
+
+pub fn energy_balance_system(
+    mut debug: ResMut<Epochs>,
+    mut query: Query<(
+        Entity,
+        &mut Temperature,
+        &GeoCoordinates,
+        &ElevationBundle,
+        &Snowpack,
+        &TileType,
+        &Neighbours,
+    )>,
+    temperature_attributes: Res<TemperatureAttributes>,
+    ecosystem_attributes: Res<EcosystemAttributes>,
+    season: Res<Season>,
+    atmosphere: Res<Atmosphere>,
+) {
+    debug.fn_order.push("energy_balance_system".to_string());
+    let energy_balance = &temperature_attributes.energy_balance;
+    let greenhouse_forcing = atmosphere.greenhouse_forcing(&temperature_attributes.atmosphere);
+
+    // every tile sees its neighbours' temperatures from before this epoch
+    let temperatures: HashMap<Entity, f32> = query
+        .iter()
+        .map(|(entity, temperature, ..)| (entity, temperature.value))
+        .collect();
+
+    for (_entity, mut temperature, geo_coordinates, elevation, snowpack, tile_type, neighbours) in
+        query.iter_mut()
+    {
+        let diffusion: f32 = neighbours
+            .ids
+            .iter()
+            .filter_map(|id| temperatures.get(id))
+            .map(|neighbour| neighbour - temperature.value)
+            .sum::<f32>()
+            * energy_balance.heat_diffusion;
+
+        let heat_capacity = match tile_type {
+            TileType::Ocean => energy_balance.ocean_heat_capacity,
+            _ => 1.0,
+        };
+
+        // snow and ice brighten the ground they cover
+        let cover = ecosystem_attributes.glaciers.cover(snowpack, elevation);
+        let albedo = tile_type.albedo() + cover * (TileType::Ice.albedo() - tile_type.albedo());
+
+        let heating = net_heating(
+            &temperature_attributes,
+            &season,
+            greenhouse_forcing,
+            temperature.value,
+            geo_coordinates.latitude,
+            &elevation.bedrock,
+            albedo,
+        );
+        temperature.value +=
+            energy_balance.temperature_response * (heating + diffusion) / heat_capacity;
+    }
+}
+
+///////////////////////////////// Wind /////////////////////////////////////////
+
+// how strongly the prevailing winds blow towards or away from the equator, compared to along
+// the latitude bands
+const MERIDIONAL_WIND_FRACTION: f32 = 0.3;
+
+/// Surface wind from the three circulation cells in each hemisphere: trade winds from the east
+/// up to 30°, westerlies up to 60° and polar easterlies beyond, following the overhead sun
+pub fn prevailing_wind(wind_attributes: &WindAttributes, season: &Season, latitude: f32) -> Wind {
+    let latitude = (latitude - season.sub_solar_latitude).clamp(-90.0, 90.0);
+    // 1 in the middle of the trade winds and polar easterlies, -1 in the westerlies and calm
+    // where the cells meet
+    let cell = (6.0 * latitude.abs()).to_radians().sin();
+    let speed = wind_attributes.prevailing_wind_speed * cell;
+    Wind {
+        east: -speed,
+        north: -latitude.signum() * speed * MERIDIONAL_WIND_FRACTION,
+    }
+}
+
+/// Sets every tile's wind to the prevailing wind plus a breeze towards warmer neighbours, where
+/// the air rises and the pressure is lower
+pub fn wind_system(
+    mut debug: ResMut<Epochs>,
+    mut query: Query<(
+        &mut Wind,
+        &Temperature,
+        &GeoCoordinates,
+        &HexCoordinates,
+        &Neighbours,
+    )>,
+    neighbour_query: Query<(&Temperature, &HexCoordinates)>,
+    grid: Res<Grid>,
+    ecosystem: Res<EcosystemAttributes>,
+    season: Res<Season>,
+) {
+    debug.fn_order.push("wind_system".to_string());
+    let wind_attributes = &ecosystem.wind;
+
+    for (mut wind, temperature, geo_coordinates, hex_coordinates, neighbours) in query.iter_mut() {
+        let thermal: Vec2 = neighbours
+            .ids
+            .iter()
+            .filter_map(|id| neighbour_query.get(*id).ok())
+            .map(|(neighbour_temperature, neighbour_hex)| {
+                grid.heading(hex_coordinates.0, neighbour_hex.0)
+                    * (neighbour_temperature.value - temperature.value)
+            })
+            .sum::<Vec2>()
+            * wind_attributes.thermal_wind_factor;
+
+        let prevailing = prevailing_wind(wind_attributes, &season, geo_coordinates.latitude);
+        *wind = Wind {
+            east: prevailing.east + thermal.x,
+            north: prevailing.north + thermal.y,
+        };
+    }
+}
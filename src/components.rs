@@ -1,5 +1,6 @@
 use bevy::prelude::*;
 use hexx::Hex;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
 use crate::terrain::TileType;
@@ -8,48 +9,59 @@ use crate::Epochs;
 ///////////////////////////////// Intermediary Components /////////////////////////////////////////
 ///
 /// This component is for display last epoch weather data
-#[derive(Debug, Clone, Copy, Component)]
+#[derive(Debug, Clone, Component, Serialize, Deserialize)]
 pub struct DebugWeatherBundle {
     pub overflow: Overflow,
     pub overflow_received: OverflowReceived,
     pub humidity_received: HumidityReceived,
     pub humidity_sent: HumiditySent,
     pub evaporation: Evaporation,
+    #[serde(default)]
+    pub transpiration: Transpiration,
     pub precipitation: Precipitation,
+    // where the overflow went, one entry per lower neighbour that received some
+    #[serde(skip)]
+    pub overflow_split: Vec<(Entity, OutgoingOverflow)>,
 }
 
-#[derive(Debug, Clone, Copy, Default, Component)]
+#[derive(Debug, Clone, Copy, Default, Component, Serialize, Deserialize)]
 pub struct HumidityReceived {
     pub value: f32,
 }
 
-#[derive(Debug, Clone, Copy, Default, Component)]
+#[derive(Debug, Clone, Copy, Default, Component, Serialize, Deserialize)]
 pub struct HumiditySent {
     pub value: f32,
 }
 
-#[derive(Debug, Clone, Copy, Default, Component)]
+#[derive(Debug, Clone, Copy, Default, Component, Serialize, Deserialize)]
 pub struct OverflowReceived {
     pub water: f32,
     pub soil: f32,
 }
 
-#[derive(Debug, Clone, Copy, Default, Component)]
+#[derive(Debug, Clone, Copy, Default, Component, Serialize, Deserialize)]
 pub struct Overflow {
     pub water: f32,
     pub soil: f32,
 }
 
-#[derive(Debug, Clone, Copy, Default, Component)]
+#[derive(Debug, Clone, Copy, Default, Component, Serialize, Deserialize)]
 pub struct Precipitation {
     pub value: f32,
 }
 
-#[derive(Debug, Clone, Copy, Default, Component)]
+#[derive(Debug, Clone, Copy, Default, Component, Serialize, Deserialize)]
 pub struct Evaporation {
     pub value: f32,
 }
 
+/// Soil water drawn up by plants and released into the air
+#[derive(Debug, Clone, Copy, Default, Component, Serialize, Deserialize)]
+pub struct Transpiration {
+    pub value: f32,
+}
+
 /////////////////// Dynamic Components /////////////////////////////////////////
 // Dynamically assigned to entities to apply weather changes
 
@@ -62,32 +74,44 @@ pub struct OutgoingOverflow {
     pub soil: f32,
 }
 
-#[derive(Debug, Clone, Component, Default)]
+#[derive(Debug, Clone, Component, Default, Serialize, Deserialize)]
 pub struct IncomingOverflow {
     pub water: f32,
     pub soil: f32,
 }
 
-#[derive(Debug, Clone, Component)]
+#[derive(Debug, Clone, Component, Serialize, Deserialize)]
 pub struct PendingHumidityRedistribution {
     pub value: f32,
+    // humidity that rained out while the wind pushed it uphill
+    #[serde(default)]
+    pub rain: f32,
 }
 
 ///////////////////////////////////////////////////////////////////////////////
 
 ////////////////////////// Components /////////////////////////////////////////
 
-#[derive(Debug, Clone, Component)]
+#[derive(Debug, Clone, Component, Serialize, Deserialize)]
 pub struct DistancesFromVolcano(pub Vec<u16>);
 
 #[derive(Debug, Clone, Component)]
 pub struct HexCoordinates(pub Hex);
 
+/// Position of a tile on the planet in degrees
 #[derive(Debug, Clone, Copy, Component)]
+pub struct GeoCoordinates {
+    pub latitude: f32,
+    pub longitude: f32,
+}
+
+#[derive(Debug, Clone, Copy, Component, Serialize, Deserialize)]
 pub struct ElevationBundle {
     pub bedrock: BedrockElevation,
     pub soil: SoilElevation,
     pub water: WaterElevation,
+    #[serde(default)]
+    pub ice: IceElevation,
 }
 
 impl ElevationBundle {
@@ -100,11 +124,17 @@ impl ElevationBundle {
             bedrock: bedrock.into(),
             soil: tile_type.into(),
             water: water_level,
+            ice: tile_type.into(),
         }
     }
+
+    /// Height of the tile's surface, with its water and ice on top
+    pub fn height(&self) -> f32 {
+        self.bedrock.value + self.soil.value + self.water.value + self.ice.value
+    }
 }
 
-#[derive(Debug, Clone, Copy, Component)]
+#[derive(Debug, Clone, Copy, Component, Serialize, Deserialize)]
 pub struct BedrockElevation {
     pub value: f32,
 }
@@ -115,7 +145,7 @@ impl From<f32> for BedrockElevation {
     }
 }
 
-#[derive(Debug, Clone, Copy, Component)]
+#[derive(Debug, Clone, Copy, Component, Serialize, Deserialize)]
 pub struct SoilElevation {
     pub value: f32,
 }
@@ -135,7 +165,7 @@ impl From<TileType> for SoilElevation {
     }
 }
 
-#[derive(Debug, Clone, Copy, Component)]
+#[derive(Debug, Clone, Copy, Component, Serialize, Deserialize)]
 pub struct WaterElevation {
     pub value: f32,
 }
@@ -162,7 +192,34 @@ impl From<TileType> for WaterElevation {
     }
 }
 
-#[derive(Debug, Clone, Copy, Component)]
+/// Thickness of the glacier ice covering a tile, as the water it holds
+#[derive(Debug, Clone, Copy, Default, Component, Serialize, Deserialize)]
+pub struct IceElevation {
+    pub value: f32,
+}
+
+impl From<f32> for IceElevation {
+    fn from(value: f32) -> Self {
+        IceElevation { value }
+    }
+}
+
+impl From<TileType> for IceElevation {
+    fn from(tile_type: TileType) -> Self {
+        match tile_type {
+            TileType::Ice => 0.5.into(),
+            _ => 0.0.into(),
+        }
+    }
+}
+
+/// Snow lying on a tile, as the water it holds, which compacts into ice over time
+#[derive(Debug, Clone, Copy, Default, Component, Serialize, Deserialize)]
+pub struct Snowpack {
+    pub value: f32,
+}
+
+#[derive(Debug, Clone, Copy, Component, Serialize, Deserialize)]
 pub struct Humidity {
     pub value: f32,
 }
@@ -184,11 +241,96 @@ impl From<TileType> for Humidity {
     }
 }
 
-#[derive(Debug, Clone, Copy, Component)]
+/// Living vegetation on a tile, as a fraction of what the richest soil can carry
+#[derive(Debug, Clone, Copy, Component, Serialize, Deserialize)]
+pub struct Biomass {
+    pub value: f32,
+}
+
+impl From<f32> for Biomass {
+    fn from(value: f32) -> Self {
+        Biomass { value }
+    }
+}
+
+impl From<TileType> for Biomass {
+    fn from(tile_type: TileType) -> Biomass {
+        match tile_type {
+            TileType::Jungle => 0.9.into(),
+            TileType::Forest => 0.7.into(),
+            TileType::Grass | TileType::Swamp => 0.4.into(),
+            TileType::Dirt | TileType::Hills => 0.1.into(),
+            TileType::Desert => 0.05.into(),
+            TileType::Ocean
+            | TileType::Water
+            | TileType::Ice
+            | TileType::Rocky
+            | TileType::Mountain
+            | TileType::Waste => 0.0.into(),
+        }
+    }
+}
+
+/// Water held in the rock beneath the soil, and how much seeped in and rose back up as a spring
+/// during the last epoch
+#[derive(Debug, Clone, Copy, Default, Component, Serialize, Deserialize)]
+pub struct Aquifer {
+    pub water: f32,
+    pub infiltration: f32,
+    pub spring: f32,
+}
+
+#[derive(Debug, Clone, Copy, Component, Serialize, Deserialize)]
 pub struct Temperature {
     pub value: f32,
 }
 
+/// Surface wind over a tile in m/s, recalculated at the start of every epoch
+#[derive(Debug, Clone, Copy, Default, Component)]
+pub struct Wind {
+    pub east: f32,
+    pub north: f32,
+}
+
+impl Wind {
+    pub fn speed(&self) -> f32 {
+        self.east.hypot(self.north)
+    }
+
+    /// Compass bearing the wind blows from, in degrees
+    pub fn bearing(&self) -> f32 {
+        (-self.east)
+            .atan2(-self.north)
+            .to_degrees()
+            .rem_euclid(360.0)
+    }
+}
+
+/// Marks a tile flooded by a lake, recalculated every epoch.
+///
+/// Kept out of the tile tables so that lakes filling and drying up don't change the order
+/// tiles are visited in, which would break continuing a saved world exactly.
+#[derive(Debug, Clone, Copy, Component)]
+#[component(storage = "SparseSet")]
+pub struct Lake {
+    // height of the lake's surface
+    pub level: f32,
+    // rim tile the lake drains over once it is full
+    pub spill: Hex,
+}
+
+/// Marks a tile that enough water flows through to form a river, recalculated every epoch.
+///
+/// Sparse for the same reason as `Lake`. Not saved, since it is rebuilt from the overflow.
+#[derive(Debug, Clone, Copy, Component)]
+#[component(storage = "SparseSet")]
+pub struct River {
+    // water that drains through the tile, from all the tiles upstream of it
+    pub discharge: f32,
+    // neighbour that receives most of it
+    pub downstream: Entity,
+}
+
 #[derive(Debug, Clone, Component)]
 pub struct Neighbours {
     pub ids: Vec<Entity>,
@@ -217,11 +359,27 @@ impl fmt::Display for HexCoordinates {
     }
 }
 
+impl fmt::Display for GeoCoordinates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let north_south = if self.latitude >= 0.0 { "N" } else { "S" };
+        let east_west = if self.longitude >= 0.0 { "E" } else { "W" };
+        write!(
+            f,
+            "{:.1}°{} {:.1}°{}",
+            self.latitude.abs(),
+            north_south,
+            self.longitude.abs(),
+            east_west
+        )
+    }
+}
+
 impl fmt::Display for ElevationBundle {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Bedrock Elevation: {}", self.bedrock.value)?;
         writeln!(f, "Soil Elevation: {}", self.soil.value)?;
-        writeln!(f, "Water Elevation: {}", self.water.value)
+        writeln!(f, "Water Elevation: {}", self.water.value)?;
+        writeln!(f, "Ice Elevation: {}", self.ice.value)
     }
 }
 
@@ -243,6 +401,34 @@ impl fmt::Display for WaterElevation {
     }
 }
 
+impl fmt::Display for IceElevation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+impl fmt::Display for Snowpack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.4}", self.value)
+    }
+}
+
+impl fmt::Display for Aquifer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.4} (infiltration {:.4}, spring {:.4})",
+            self.water, self.infiltration, self.spring
+        )
+    }
+}
+
+impl fmt::Display for Biomass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.3}", self.value)
+    }
+}
+
 impl fmt::Display for Humidity {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.value)
@@ -255,6 +441,12 @@ impl fmt::Display for Overflow {
     }
 }
 
+impl fmt::Display for OutgoingOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "water: {} soil: {}", self.water, self.soil)
+    }
+}
+
 impl fmt::Display for OverflowReceived {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "water: {} soil: {}", self.water, self.soil)
@@ -273,12 +465,36 @@ impl fmt::Display for Evaporation {
     }
 }
 
+impl fmt::Display for Transpiration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
 impl fmt::Display for Temperature {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.value)
     }
 }
 
+impl fmt::Display for Wind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} m/s from {:.0}°", self.speed(), self.bearing())
+    }
+}
+
+impl fmt::Display for Lake {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "level {:.2}, spills over {:?}", self.level, self.spill)
+    }
+}
+
+impl fmt::Display for River {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "discharge {:.3}", self.discharge)
+    }
+}
+
 impl fmt::Display for Neighbours {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Neighbours: {:?}", self.ids)
@@ -0,0 +1,1080 @@
+use bevy::prelude::*;
+use serde::de::{self, Visitor};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::aquifer::AquiferAttributes;
+use crate::atmosphere::AtmosphereAttributes;
+use crate::biomes::BiomeTable;
+use crate::climate::Orbit;
+use crate::glaciers::GlacierAttributes;
+use crate::grid::MapShape;
+use crate::hydrology::FlowRouting;
+use crate::map_generation::{NoiseTerrain, PlateTerrain, TerrainGeneratorConfig};
+use crate::vegetation::VegetationAttributes;
+
+/// Built-in world config that user files are layered over
+const DEFAULTS: &str = include_str!("../defaults.json");
+
+// environment variable used to pick a config file when no flag is given
+pub const CONFIG_ENV_VAR: &str = "TERRAFLOW_CONFIG";
+
+#[derive(Debug, Clone, Deserialize, Resource)]
+pub struct Config {
+    pub(crate) hex_size: f32,
+    pub(crate) map_radius: u16,
+    pub(crate) erosion_factor: f32,
+    pub(crate) bedrock_erodibility: f32,
+    pub(crate) precipitation_factor: f32,
+    pub(crate) evaporation_factor: f32,
+    pub(crate) highest_elevation: f32,
+    pub(crate) vulcanism: f32,
+    pub(crate) mountain_spread: f32,
+    pub(crate) elevation_increment: f32,
+    pub(crate) epoch_increment: f32,
+    pub(crate) sea_level: f32,
+    pub(crate) terrain_change_sensitivity: f32,
+    pub(crate) mountain_point: f32,
+    pub(crate) hill_point: f32,
+    pub(crate) soil_and_water_height_display_factor: f32,
+    pub(crate) base_temperature: f32,
+    pub(crate) latitude_temperature_variation: f32,
+    pub(crate) altitude_temperature_variation: f32,
+    pub(crate) solar_constant: f32,
+    pub(crate) insolation_variation: f32,
+    pub(crate) outgoing_radiation_base: f32,
+    pub(crate) outgoing_radiation_slope: f32,
+    pub(crate) heat_diffusion: f32,
+    pub(crate) ocean_heat_capacity: f32,
+    pub(crate) temperature_response: f32,
+    pub(crate) orbit: Orbit,
+    pub(crate) atmosphere: AtmosphereAttributes,
+    pub(crate) overflow_factor: f32,
+    pub(crate) flow_routing: FlowRouting,
+    pub(crate) river_threshold: f32,
+    pub(crate) humidity_escape_factor: f32,
+    pub(crate) strict_water_conservation: bool,
+    pub(crate) prevailing_wind_speed: f32,
+    pub(crate) thermal_wind_factor: f32,
+    pub(crate) orographic_rain_factor: f32,
+    pub(crate) vegetation: VegetationAttributes,
+    pub(crate) aquifer: AquiferAttributes,
+    pub(crate) glaciers: GlacierAttributes,
+    pub(crate) terrain_generator: TerrainGeneratorConfig,
+    pub(crate) map_shape: MapShape,
+    pub(crate) biomes: BiomeTable,
+}
+
+impl Config {
+    pub fn defaults() -> Self {
+        serde_json::from_str(DEFAULTS).expect("built-in defaults.json is invalid")
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, ConfigError> {
+        let config_str = std::fs::read_to_string(path).map_err(|error| ConfigError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Self::from_json(&config_str)
+    }
+
+    /// Parses a (possibly partial) config, filling in any keys it leaves out from the defaults
+    pub fn from_json(config_str: &str) -> Result<Self, ConfigError> {
+        let mut merged: Map<String, Value> =
+            serde_json::from_str(DEFAULTS).expect("built-in defaults.json is invalid");
+        let overrides: Value = serde_json::from_str(config_str).map_err(ConfigError::Parse)?;
+
+        let mut issues = vec![];
+        match overrides {
+            Value::Object(overrides) => merge(&mut merged, overrides, "", &mut issues),
+            _ => issues.push(ConfigIssue::WrongType {
+                key: "<root>".to_string(),
+                expected: "an object",
+            }),
+        }
+
+        for key in struct_fields::<Config>() {
+            if !merged.contains_key(*key) {
+                issues.push(ConfigIssue::MissingKey(key.to_string()));
+            }
+        }
+
+        if !issues.is_empty() {
+            return Err(ConfigError::Invalid(issues));
+        }
+
+        let config: Config =
+            serde_json::from_value(Value::Object(merged)).map_err(ConfigError::Parse)?;
+        let issues = config.validate();
+        if !issues.is_empty() {
+            return Err(ConfigError::Invalid(issues));
+        }
+
+        Ok(config)
+    }
+
+    /// Checks that every value is within the range the simulation can work with
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = vec![];
+        let mut check = |key: &str, value: f32, valid: bool, reason: &'static str| {
+            if !valid {
+                issues.push(ConfigIssue::OutOfRange {
+                    key: key.to_string(),
+                    value,
+                    reason,
+                });
+            }
+        };
+
+        check(
+            "hex_size",
+            self.hex_size,
+            self.hex_size > 0.0,
+            "must be positive",
+        );
+        check(
+            "map_radius",
+            self.map_radius as f32,
+            self.map_radius > 0,
+            "must be at least 1",
+        );
+        check(
+            "highest_elevation",
+            self.highest_elevation,
+            self.highest_elevation > 0.0,
+            "must be positive",
+        );
+        check(
+            "sea_level",
+            self.sea_level,
+            self.sea_level >= 0.0,
+            "must not be negative",
+        );
+        check(
+            "sea_level",
+            self.sea_level,
+            self.sea_level < self.highest_elevation,
+            "must be below highest_elevation",
+        );
+        check(
+            "vulcanism",
+            self.vulcanism,
+            self.vulcanism >= 1.0,
+            "must be at least 1 volcano",
+        );
+        check(
+            "mountain_spread",
+            self.mountain_spread,
+            self.mountain_spread > 0.0,
+            "must be positive",
+        );
+        check(
+            "elevation_increment",
+            self.elevation_increment,
+            self.elevation_increment > 0.0,
+            "must be positive",
+        );
+        check(
+            "epoch_increment",
+            self.epoch_increment,
+            self.epoch_increment >= 0.0,
+            "must not be negative",
+        );
+        check(
+            "mountain_point",
+            self.mountain_point,
+            self.mountain_point > 0.0 && self.mountain_point <= 1.0,
+            "must be between 0 and 1",
+        );
+        check(
+            "hill_point",
+            self.hill_point,
+            self.hill_point > 0.0 && self.hill_point <= 1.0,
+            "must be between 0 and 1",
+        );
+        check(
+            "hill_point",
+            self.hill_point,
+            self.hill_point <= self.mountain_point,
+            "must not be above mountain_point",
+        );
+
+        for (key, value) in [
+            ("erosion_factor", self.erosion_factor),
+            ("precipitation_factor", self.precipitation_factor),
+            ("evaporation_factor", self.evaporation_factor),
+            (
+                "terrain_change_sensitivity",
+                self.terrain_change_sensitivity,
+            ),
+            (
+                "soil_and_water_height_display_factor",
+                self.soil_and_water_height_display_factor,
+            ),
+            (
+                "latitude_temperature_variation",
+                self.latitude_temperature_variation,
+            ),
+            (
+                "altitude_temperature_variation",
+                self.altitude_temperature_variation,
+            ),
+        ] {
+            check(key, value, value >= 0.0, "must not be negative");
+        }
+
+        check(
+            "solar_constant",
+            self.solar_constant,
+            self.solar_constant >= 0.0,
+            "must not be negative",
+        );
+        check(
+            "insolation_variation",
+            self.insolation_variation,
+            (0.0..1.0).contains(&self.insolation_variation),
+            "must be at least 0 and below 1 so that the poles get some sunlight",
+        );
+        check(
+            "outgoing_radiation_slope",
+            self.outgoing_radiation_slope,
+            self.outgoing_radiation_slope > 0.0,
+            "must be positive so that temperatures settle",
+        );
+        check(
+            "heat_diffusion",
+            self.heat_diffusion,
+            self.heat_diffusion >= 0.0,
+            "must not be negative",
+        );
+        check(
+            "ocean_heat_capacity",
+            self.ocean_heat_capacity,
+            self.ocean_heat_capacity >= 1.0,
+            "must be at least 1, the heat capacity of land",
+        );
+        // each epoch is one explicit step, which overshoots when it is too long
+        let stiffness = self.outgoing_radiation_slope + 6.0 * self.heat_diffusion;
+        check(
+            "temperature_response",
+            self.temperature_response,
+            self.temperature_response > 0.0 && self.temperature_response * stiffness < 1.0,
+            "must be positive and below 1 / (outgoing_radiation_slope + 6 * heat_diffusion)",
+        );
+
+        check(
+            "orbit.axial_tilt",
+            self.orbit.axial_tilt,
+            (0.0..=90.0).contains(&self.orbit.axial_tilt),
+            "must be between 0 and 90 degrees",
+        );
+        check(
+            "orbit.year_length",
+            self.orbit.year_length as f32,
+            self.orbit.year_length >= 1,
+            "must be at least 1 epoch",
+        );
+        check(
+            "orbit.eccentricity",
+            self.orbit.eccentricity,
+            (0.0..0.9).contains(&self.orbit.eccentricity),
+            "must be at least 0 and below 0.9",
+        );
+
+        let composition = &self.atmosphere.composition;
+        for (key, value) in [
+            ("atmosphere.composition.co2", composition.co2),
+            ("atmosphere.composition.o2", composition.o2),
+            ("atmosphere.composition.n2", composition.n2),
+            ("atmosphere.composition.ch4", composition.ch4),
+            ("atmosphere.co2_forcing", self.atmosphere.co2_forcing),
+            ("atmosphere.ch4_forcing", self.atmosphere.ch4_forcing),
+            (
+                "atmosphere.volcanic_outgassing",
+                self.atmosphere.volcanic_outgassing,
+            ),
+        ] {
+            check(key, value, value >= 0.0, "must not be negative");
+        }
+        for (key, value) in [
+            ("atmosphere.photosynthesis", self.atmosphere.photosynthesis),
+            ("atmosphere.ocean_uptake", self.atmosphere.ocean_uptake),
+        ] {
+            check(
+                key,
+                value,
+                (0.0..=1.0).contains(&value),
+                "must be between 0 and 1",
+            );
+        }
+
+        for (key, value) in [
+            ("bedrock_erodibility", self.bedrock_erodibility),
+            ("overflow_factor", self.overflow_factor),
+            ("humidity_escape_factor", self.humidity_escape_factor),
+        ] {
+            check(
+                key,
+                value,
+                (0.0..=1.0).contains(&value),
+                "must be between 0 and 1",
+            );
+        }
+
+        match self.flow_routing {
+            FlowRouting::SteepestDescent => (),
+            FlowRouting::MultipleFlow { exponent } => check(
+                "flow_routing.exponent",
+                exponent,
+                exponent > 0.0,
+                "must be positive",
+            ),
+        }
+
+        check(
+            "river_threshold",
+            self.river_threshold,
+            self.river_threshold > 0.0,
+            "must be positive",
+        );
+
+        check(
+            "prevailing_wind_speed",
+            self.prevailing_wind_speed,
+            self.prevailing_wind_speed > 0.0,
+            "must be positive",
+        );
+        for (key, value) in [
+            ("thermal_wind_factor", self.thermal_wind_factor),
+            ("orographic_rain_factor", self.orographic_rain_factor),
+        ] {
+            check(key, value, value >= 0.0, "must not be negative");
+        }
+
+        let vegetation = &self.vegetation;
+        for (key, value) in [
+            ("vegetation.growth_rate", vegetation.growth_rate),
+            ("vegetation.dieback_rate", vegetation.dieback_rate),
+            ("vegetation.seed_dispersal", vegetation.seed_dispersal),
+            ("vegetation.drought_moisture", vegetation.drought_moisture),
+        ] {
+            check(
+                key,
+                value,
+                (0.0..=1.0).contains(&value),
+                "must be between 0 and 1",
+            );
+        }
+        check(
+            "vegetation.full_cover_soil",
+            vegetation.full_cover_soil,
+            vegetation.full_cover_soil > 0.0,
+            "must be positive",
+        );
+        check(
+            "vegetation.optimum_temperature",
+            vegetation.optimum_temperature,
+            vegetation.optimum_temperature > vegetation.frost_temperature,
+            "must be above frost_temperature",
+        );
+        check(
+            "vegetation.heat_temperature",
+            vegetation.heat_temperature,
+            vegetation.heat_temperature > vegetation.optimum_temperature,
+            "must be above optimum_temperature",
+        );
+
+        let aquifer = &self.aquifer;
+        check(
+            "aquifer.depth",
+            aquifer.depth,
+            aquifer.depth > 0.0,
+            "must be positive",
+        );
+        check(
+            "aquifer.porosity",
+            aquifer.porosity,
+            aquifer.porosity > 0.0 && aquifer.porosity <= 1.0,
+            "must be above 0 and at most 1",
+        );
+        for (key, value) in [
+            ("aquifer.infiltration_rate", aquifer.infiltration_rate),
+            ("aquifer.conductivity", aquifer.conductivity),
+        ] {
+            check(
+                key,
+                value,
+                (0.0..=1.0).contains(&value),
+                "must be between 0 and 1",
+            );
+        }
+
+        let glaciers = &self.glaciers;
+        for (key, value) in [
+            ("glaciers.compaction_rate", glaciers.compaction_rate),
+            ("glaciers.flow_rate", glaciers.flow_rate),
+        ] {
+            check(
+                key,
+                value,
+                (0.0..=1.0).contains(&value),
+                "must be between 0 and 1",
+            );
+        }
+        for (key, value) in [
+            ("glaciers.melt_rate", glaciers.melt_rate),
+            ("glaciers.erosion_rate", glaciers.erosion_rate),
+        ] {
+            check(key, value, value >= 0.0, "must not be negative");
+        }
+        check(
+            "glaciers.full_cover_depth",
+            glaciers.full_cover_depth,
+            glaciers.full_cover_depth > 0.0,
+            "must be positive",
+        );
+
+        match self.map_shape {
+            MapShape::Hexagon => (),
+            MapShape::Sphere { subdivisions } => check(
+                "map_shape.subdivisions",
+                subdivisions as f32,
+                (1..=100).contains(&subdivisions),
+                "must be between 1 and 100",
+            ),
+            MapShape::Cylinder { width, height } => {
+                check(
+                    "map_shape.width",
+                    width as f32,
+                    width >= 3,
+                    "must be at least 3 so the seam doesn't join a tile to itself",
+                );
+                check(
+                    "map_shape.height",
+                    height as f32,
+                    height >= 1,
+                    "must be at least 1",
+                );
+            }
+        }
+
+        match &self.terrain_generator {
+            TerrainGeneratorConfig::Volcanoes(_) => (),
+            TerrainGeneratorConfig::Noise(noise) => noise.validate(&mut check),
+            TerrainGeneratorConfig::Plates(plates) => plates.validate(&mut check),
+        }
+
+        self.biomes.validate(&mut check);
+        match self.biomes.biomes.last() {
+            Some(biome) if biome.is_catch_all() => (),
+            _ => issues.push(ConfigIssue::Invalid {
+                key: "biomes".to_string(),
+                reason: "must end with a biome without bands, so that every tile has a biome",
+            }),
+        }
+
+        issues
+    }
+}
+
+impl BiomeTable {
+    fn validate(&self, check: &mut impl FnMut(&str, f32, bool, &'static str)) {
+        for (index, biome) in self.biomes.iter().enumerate() {
+            for (name, band) in [
+                ("temperature", biome.temperature),
+                ("humidity", biome.humidity),
+                ("altitude", biome.altitude),
+            ] {
+                if let (Some(min), Some(max)) = (band.min, band.max) {
+                    check(
+                        &format!("biomes[{}].{}.min", index, name),
+                        min,
+                        min <= max,
+                        "must not be above max",
+                    );
+                }
+            }
+
+            for (tile_type, weight) in &biome.tiles {
+                check(
+                    &format!("biomes[{}].tiles.{:?}", index, tile_type),
+                    *weight,
+                    *weight >= 0.0,
+                    "must not be negative",
+                );
+            }
+            let total_weight: f32 = biome.tiles.iter().map(|(_, weight)| weight).sum();
+            check(
+                &format!("biomes[{}].tiles", index),
+                total_weight,
+                total_weight > 0.0,
+                "weights must add up to more than 0",
+            );
+        }
+    }
+}
+
+impl NoiseTerrain {
+    fn validate(&self, check: &mut impl FnMut(&str, f32, bool, &'static str)) {
+        check(
+            "terrain_generator.frequency",
+            self.frequency as f32,
+            self.frequency > 0.0,
+            "must be positive",
+        );
+        check(
+            "terrain_generator.octaves",
+            self.octaves as f32,
+            (1..=NoiseTerrain::MAX_OCTAVES).contains(&self.octaves),
+            "must be between 1 and 32",
+        );
+        check(
+            "terrain_generator.lacunarity",
+            self.lacunarity as f32,
+            self.lacunarity > 0.0,
+            "must be positive",
+        );
+        check(
+            "terrain_generator.persistence",
+            self.persistence as f32,
+            self.persistence > 0.0,
+            "must be positive",
+        );
+        check(
+            "terrain_generator.falloff",
+            self.falloff,
+            (0.0..=1.0).contains(&self.falloff),
+            "must be between 0 and 1",
+        );
+    }
+}
+
+/// Layers `overrides` over `base`, recording keys that `base` doesn't know about
+fn merge(
+    base: &mut Map<String, Value>,
+    overrides: Map<String, Value>,
+    prefix: &str,
+    issues: &mut Vec<ConfigIssue>,
+) {
+    for (key, value) in overrides {
+        let path = format!("{}{}", prefix, key);
+        let Some(default) = base.get_mut(&key) else {
+            issues.push(ConfigIssue::UnknownKey(path));
+            continue;
+        };
+
+        match (default, value) {
+            // switching to a different variant replaces the defaults instead of merging with them
+            (Value::Object(default), Value::Object(value))
+                if value.contains_key("type") && value.get("type") != default.get("type") =>
+            {
+                *default = value
+            }
+            (Value::Object(default), Value::Object(value)) => {
+                merge(default, value, &format!("{}.", path), issues)
+            }
+            (Value::Number(_), value) if !value.is_number() => {
+                issues.push(ConfigIssue::WrongType {
+                    key: path,
+                    expected: "a number",
+                })
+            }
+            (default, value) => *default = value,
+        }
+    }
+}
+
+/// Field names of a struct, read from its `Deserialize` impl
+fn struct_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de, 'a> de::Deserializer<'de> for FieldNames<'a> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("only reading field names"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+            ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+impl PlateTerrain {
+    fn validate(&self, check: &mut impl FnMut(&str, f32, bool, &'static str)) {
+        check(
+            "terrain_generator.plates",
+            self.plates as f32,
+            self.plates >= 2,
+            "must be at least 2",
+        );
+        check(
+            "terrain_generator.oceanic_fraction",
+            self.oceanic_fraction,
+            (0.0..=1.0).contains(&self.oceanic_fraction),
+            "must be between 0 and 1",
+        );
+        for (key, value) in [
+            ("terrain_generator.uplift", self.uplift),
+            ("terrain_generator.trench_depth", self.trench_depth),
+            ("terrain_generator.rift_depth", self.rift_depth),
+            ("terrain_generator.roughness", self.roughness),
+        ] {
+            check(key, value, value >= 0.0, "must not be negative");
+        }
+    }
+}
+
+////////////////////////////////////////// Errors /////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigIssue {
+    UnknownKey(String),
+    MissingKey(String),
+    WrongType {
+        key: String,
+        expected: &'static str,
+    },
+    OutOfRange {
+        key: String,
+        value: f32,
+        reason: &'static str,
+    },
+    Invalid {
+        key: String,
+        reason: &'static str,
+    },
+    UnknownName {
+        key: String,
+        name: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Parse(serde_json::Error),
+    Invalid(Vec<ConfigIssue>),
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigIssue::UnknownKey(key) => write!(f, "unknown key `{}`", key),
+            ConfigIssue::MissingKey(key) => write!(f, "missing key `{}`", key),
+            ConfigIssue::WrongType { key, expected } => {
+                write!(f, "`{}` should be {}", key, expected)
+            }
+            ConfigIssue::OutOfRange { key, value, reason } => {
+                write!(f, "`{}` is {} but {}", key, value, reason)
+            }
+            ConfigIssue::Invalid { key, reason } => write!(f, "`{}` {}", key, reason),
+            ConfigIssue::UnknownName { key, name } => {
+                write!(f, "`{}` refers to `{}`, which doesn't exist", key, name)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => {
+                write!(f, "could not read config {}: {}", path.display(), error)
+            }
+            ConfigError::Parse(error) => write!(f, "could not parse config: {}", error),
+            ConfigError::Invalid(issues) => {
+                write!(f, "invalid config:")?;
+                for issue in issues {
+                    write!(f, "\n  - {}", issue)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issues(config: &str) -> Vec<ConfigIssue> {
+        match Config::from_json(config) {
+            Err(ConfigError::Invalid(issues)) => issues,
+            other => panic!("expected {} to be invalid, got {:?}", config, other),
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(Config::defaults().validate(), vec![]);
+        assert!(Config::from_json("{}").is_ok());
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_with_their_path() {
+        assert_eq!(
+            issues(r#"{ "map_radiuss": 10 }"#),
+            vec![ConfigIssue::UnknownKey("map_radiuss".to_string())]
+        );
+        assert_eq!(
+            issues(r#"{ "orbit": { "tilt": 10.0 } }"#),
+            vec![ConfigIssue::UnknownKey("orbit.tilt".to_string())]
+        );
+    }
+
+    #[test]
+    fn wrong_types_are_reported() {
+        assert_eq!(
+            issues(r#"{ "map_radius": "large" }"#),
+            vec![ConfigIssue::WrongType {
+                key: "map_radius".to_string(),
+                expected: "a number",
+            }]
+        );
+        assert_eq!(
+            issues("[]"),
+            vec![ConfigIssue::WrongType {
+                key: "<root>".to_string(),
+                expected: "an object",
+            }]
+        );
+    }
+
+    #[test]
+    fn nested_overrides_keep_the_other_defaults() {
+        let defaults = Config::defaults();
+        let config = Config::from_json(
+            r#"{ "orbit": { "axial_tilt": 10.0 }, "atmosphere": { "composition": { "co2": 0.08 } } }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.orbit.axial_tilt, 10.0);
+        assert_eq!(config.orbit.year_length, defaults.orbit.year_length);
+        assert_eq!(config.orbit.eccentricity, defaults.orbit.eccentricity);
+        assert_eq!(config.atmosphere.composition.co2, 0.08);
+        assert_eq!(
+            config.atmosphere.composition.o2,
+            defaults.atmosphere.composition.o2
+        );
+        assert_eq!(
+            config.atmosphere.co2_forcing,
+            defaults.atmosphere.co2_forcing
+        );
+        assert_eq!(config.map_radius, defaults.map_radius);
+    }
+
+    #[test]
+    fn changing_type_replaces_the_defaults() {
+        // the default volcano generator has no octaves, so merging would report it as unknown
+        let config =
+            Config::from_json(r#"{ "terrain_generator": { "type": "noise", "octaves": 3 } }"#)
+                .unwrap();
+        match config.terrain_generator {
+            TerrainGeneratorConfig::Noise(noise) => {
+                assert_eq!(noise.octaves, 3);
+                assert_eq!(noise.frequency, NoiseTerrain::default().frequency);
+            }
+            other => panic!("expected the noise generator, got {:?}", other),
+        }
+
+        let config = Config::from_json(
+            r#"{ "map_shape": { "type": "cylinder", "width": 20, "height": 10 } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.map_shape,
+            MapShape::Cylinder {
+                width: 20,
+                height: 10
+            }
+        );
+    }
+
+    #[test]
+    fn out_of_range_values_are_reported() {
+        for (config, key) in [
+            (r#"{ "hex_size": 0.0 }"#, "hex_size"),
+            (r#"{ "map_radius": 0 }"#, "map_radius"),
+            (r#"{ "highest_elevation": 0.0 }"#, "highest_elevation"),
+            (r#"{ "sea_level": -1.0 }"#, "sea_level"),
+            (r#"{ "sea_level": 20.0 }"#, "sea_level"),
+            (r#"{ "vulcanism": 0 }"#, "vulcanism"),
+            (r#"{ "mountain_spread": 0.0 }"#, "mountain_spread"),
+            (r#"{ "elevation_increment": 0.0 }"#, "elevation_increment"),
+            (r#"{ "epoch_increment": -1.0 }"#, "epoch_increment"),
+            (r#"{ "mountain_point": 1.5 }"#, "mountain_point"),
+            (r#"{ "hill_point": 0.0 }"#, "hill_point"),
+            (r#"{ "hill_point": 0.9 }"#, "hill_point"),
+            (r#"{ "erosion_factor": -1.0 }"#, "erosion_factor"),
+            (
+                r#"{ "precipitation_factor": -1.0 }"#,
+                "precipitation_factor",
+            ),
+            (r#"{ "evaporation_factor": -1.0 }"#, "evaporation_factor"),
+            (
+                r#"{ "terrain_change_sensitivity": -1.0 }"#,
+                "terrain_change_sensitivity",
+            ),
+            (
+                r#"{ "soil_and_water_height_display_factor": -1.0 }"#,
+                "soil_and_water_height_display_factor",
+            ),
+            (
+                r#"{ "latitude_temperature_variation": -1.0 }"#,
+                "latitude_temperature_variation",
+            ),
+            (
+                r#"{ "altitude_temperature_variation": -1.0 }"#,
+                "altitude_temperature_variation",
+            ),
+            (r#"{ "solar_constant": -1.0 }"#, "solar_constant"),
+            (r#"{ "insolation_variation": 1.0 }"#, "insolation_variation"),
+            (
+                r#"{ "outgoing_radiation_slope": 0.0 }"#,
+                "outgoing_radiation_slope",
+            ),
+            (r#"{ "heat_diffusion": -1.0 }"#, "heat_diffusion"),
+            (r#"{ "ocean_heat_capacity": 0.5 }"#, "ocean_heat_capacity"),
+            (r#"{ "temperature_response": 0.0 }"#, "temperature_response"),
+            (r#"{ "temperature_response": 0.2 }"#, "temperature_response"),
+            (r#"{ "orbit": { "axial_tilt": 91.0 } }"#, "orbit.axial_tilt"),
+            (r#"{ "orbit": { "year_length": 0 } }"#, "orbit.year_length"),
+            (
+                r#"{ "orbit": { "eccentricity": 0.9 } }"#,
+                "orbit.eccentricity",
+            ),
+            (
+                r#"{ "atmosphere": { "composition": { "co2": -1.0 } } }"#,
+                "atmosphere.composition.co2",
+            ),
+            (
+                r#"{ "atmosphere": { "composition": { "o2": -1.0 } } }"#,
+                "atmosphere.composition.o2",
+            ),
+            (
+                r#"{ "atmosphere": { "composition": { "n2": -1.0 } } }"#,
+                "atmosphere.composition.n2",
+            ),
+            (
+                r#"{ "atmosphere": { "composition": { "ch4": -1.0 } } }"#,
+                "atmosphere.composition.ch4",
+            ),
+            (
+                r#"{ "atmosphere": { "co2_forcing": -1.0 } }"#,
+                "atmosphere.co2_forcing",
+            ),
+            (
+                r#"{ "atmosphere": { "ch4_forcing": -1.0 } }"#,
+                "atmosphere.ch4_forcing",
+            ),
+            (
+                r#"{ "atmosphere": { "volcanic_outgassing": -1.0 } }"#,
+                "atmosphere.volcanic_outgassing",
+            ),
+            (
+                r#"{ "atmosphere": { "photosynthesis": 2.0 } }"#,
+                "atmosphere.photosynthesis",
+            ),
+            (
+                r#"{ "atmosphere": { "ocean_uptake": 2.0 } }"#,
+                "atmosphere.ocean_uptake",
+            ),
+            (r#"{ "bedrock_erodibility": 2.0 }"#, "bedrock_erodibility"),
+            (r#"{ "overflow_factor": 2.0 }"#, "overflow_factor"),
+            (
+                r#"{ "humidity_escape_factor": 2.0 }"#,
+                "humidity_escape_factor",
+            ),
+            (
+                r#"{ "flow_routing": { "type": "multiple_flow", "exponent": 0.0 } }"#,
+                "flow_routing.exponent",
+            ),
+            (r#"{ "river_threshold": 0.0 }"#, "river_threshold"),
+            (
+                r#"{ "prevailing_wind_speed": 0.0 }"#,
+                "prevailing_wind_speed",
+            ),
+            (r#"{ "thermal_wind_factor": -1.0 }"#, "thermal_wind_factor"),
+            (
+                r#"{ "orographic_rain_factor": -1.0 }"#,
+                "orographic_rain_factor",
+            ),
+            (
+                r#"{ "vegetation": { "growth_rate": 2.0 } }"#,
+                "vegetation.growth_rate",
+            ),
+            (
+                r#"{ "vegetation": { "dieback_rate": 2.0 } }"#,
+                "vegetation.dieback_rate",
+            ),
+            (
+                r#"{ "vegetation": { "seed_dispersal": 2.0 } }"#,
+                "vegetation.seed_dispersal",
+            ),
+            (
+                r#"{ "vegetation": { "drought_moisture": 2.0 } }"#,
+                "vegetation.drought_moisture",
+            ),
+            (
+                r#"{ "vegetation": { "full_cover_soil": 0.0 } }"#,
+                "vegetation.full_cover_soil",
+            ),
+            (
+                r#"{ "vegetation": { "optimum_temperature": -5.0 } }"#,
+                "vegetation.optimum_temperature",
+            ),
+            (
+                r#"{ "vegetation": { "heat_temperature": 20.0 } }"#,
+                "vegetation.heat_temperature",
+            ),
+            (r#"{ "aquifer": { "depth": 0.0 } }"#, "aquifer.depth"),
+            (r#"{ "aquifer": { "porosity": 0.0 } }"#, "aquifer.porosity"),
+            (
+                r#"{ "aquifer": { "infiltration_rate": 2.0 } }"#,
+                "aquifer.infiltration_rate",
+            ),
+            (
+                r#"{ "aquifer": { "conductivity": 2.0 } }"#,
+                "aquifer.conductivity",
+            ),
+            (
+                r#"{ "glaciers": { "compaction_rate": 2.0 } }"#,
+                "glaciers.compaction_rate",
+            ),
+            (
+                r#"{ "glaciers": { "flow_rate": 2.0 } }"#,
+                "glaciers.flow_rate",
+            ),
+            (
+                r#"{ "glaciers": { "melt_rate": -1.0 } }"#,
+                "glaciers.melt_rate",
+            ),
+            (
+                r#"{ "glaciers": { "erosion_rate": -1.0 } }"#,
+                "glaciers.erosion_rate",
+            ),
+            (
+                r#"{ "glaciers": { "full_cover_depth": 0.0 } }"#,
+                "glaciers.full_cover_depth",
+            ),
+            (
+                r#"{ "map_shape": { "type": "sphere", "subdivisions": 0 } }"#,
+                "map_shape.subdivisions",
+            ),
+            (
+                r#"{ "map_shape": { "type": "cylinder", "width": 2, "height": 10 } }"#,
+                "map_shape.width",
+            ),
+            (
+                r#"{ "map_shape": { "type": "cylinder", "width": 20, "height": 0 } }"#,
+                "map_shape.height",
+            ),
+            (
+                r#"{ "terrain_generator": { "type": "noise", "frequency": 0.0 } }"#,
+                "terrain_generator.frequency",
+            ),
+            (
+                r#"{ "terrain_generator": { "type": "noise", "octaves": 0 } }"#,
+                "terrain_generator.octaves",
+            ),
+            (
+                r#"{ "terrain_generator": { "type": "noise", "lacunarity": 0.0 } }"#,
+                "terrain_generator.lacunarity",
+            ),
+            (
+                r#"{ "terrain_generator": { "type": "noise", "persistence": 0.0 } }"#,
+                "terrain_generator.persistence",
+            ),
+            (
+                r#"{ "terrain_generator": { "type": "noise", "falloff": 2.0 } }"#,
+                "terrain_generator.falloff",
+            ),
+            (
+                r#"{ "terrain_generator": { "type": "plates", "plates": 1 } }"#,
+                "terrain_generator.plates",
+            ),
+            (
+                r#"{ "terrain_generator": { "type": "plates", "oceanic_fraction": 2.0 } }"#,
+                "terrain_generator.oceanic_fraction",
+            ),
+            (
+                r#"{ "terrain_generator": { "type": "plates", "uplift": -1.0 } }"#,
+                "terrain_generator.uplift",
+            ),
+            (
+                r#"{ "terrain_generator": { "type": "plates", "trench_depth": -1.0 } }"#,
+                "terrain_generator.trench_depth",
+            ),
+            (
+                r#"{ "terrain_generator": { "type": "plates", "rift_depth": -1.0 } }"#,
+                "terrain_generator.rift_depth",
+            ),
+            (
+                r#"{ "terrain_generator": { "type": "plates", "roughness": -1.0 } }"#,
+                "terrain_generator.roughness",
+            ),
+            (
+                r#"{ "biomes": [
+                    { "temperature": { "min": 10.0, "max": 0.0 }, "tiles": [["Ice", 1.0]] },
+                    { "tiles": [["Grass", 1.0]] }
+                ] }"#,
+                "biomes[0].temperature.min",
+            ),
+            (
+                r#"{ "biomes": [{ "tiles": [["Grass", 1.0], ["Dirt", -1.0]] }] }"#,
+                "biomes[0].tiles.Dirt",
+            ),
+            (
+                r#"{ "biomes": [{ "tiles": [["Grass", 0.0]] }] }"#,
+                "biomes[0].tiles",
+            ),
+        ] {
+            let issues = issues(config);
+            assert!(
+                issues.iter().any(
+                    |issue| matches!(issue, ConfigIssue::OutOfRange { key: k, .. } if k == key)
+                ),
+                "expected {} to report `{}`, got {:?}",
+                config,
+                key,
+                issues
+            );
+        }
+    }
+
+    #[test]
+    fn biomes_must_end_with_a_catch_all() {
+        assert_eq!(
+            issues(r#"{ "biomes": [{ "temperature": { "max": 0.0 }, "tiles": [["Ice", 1.0]] }] }"#),
+            vec![ConfigIssue::Invalid {
+                key: "biomes".to_string(),
+                reason: "must end with a biome without bands, so that every tile has a biome",
+            }]
+        );
+    }
+}
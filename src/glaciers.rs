@@ -0,0 +1,274 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::components::{DebugWeatherBundle, ElevationBundle, Neighbours, Snowpack, Temperature};
+use crate::config::Config;
+use crate::sediment::SedimentBudget;
+use crate::terrain::TileType;
+use crate::water_budget::WaterBudget;
+use crate::world::EcosystemAttributes;
+use crate::Epochs;
+
+///////////////////////////////// Glaciers /////////////////////////////////////////
+
+/// How snow settles, turns to ice and flows, melts and grinds down the rock beneath, set by the
+/// `glaciers` config key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GlacierAttributes {
+    // degrees at or below which rain falls as snow, and above which snow and ice melt
+    pub snow_temperature: f32,
+    // share of the snowpack that compacts into ice every epoch
+    pub compaction_rate: f32,
+    // water melted every epoch per degree above snow_temperature
+    pub melt_rate: f32,
+    // share of a glacier that flows towards a lower neighbour every epoch per unit of drop
+    pub flow_rate: f32,
+    // bedrock ground down for every unit of ice that flows off a tile
+    pub erosion_rate: f32,
+    // depth of snow and ice that covers the ground completely, making it as bright as an ice sheet
+    pub full_cover_depth: f32,
+}
+
+impl Default for GlacierAttributes {
+    fn default() -> Self {
+        Config::defaults().glaciers
+    }
+}
+
+impl GlacierAttributes {
+    /// Share of a tile hidden under snow and ice
+    pub fn cover(&self, snowpack: &Snowpack, elevation: &ElevationBundle) -> f32 {
+        ((snowpack.value + elevation.ice.value) / self.full_cover_depth).clamp(0.0, 1.0)
+    }
+}
+
+/// Rain that fell on land at or below the snow temperature this epoch settles as snow instead
+pub fn snowfall_system(
+    mut debug: ResMut<Epochs>,
+    mut query: Query<(
+        &mut ElevationBundle,
+        &mut Snowpack,
+        &DebugWeatherBundle,
+        &Temperature,
+        &TileType,
+    )>,
+    ecosystem_attributes: Res<EcosystemAttributes>,
+    mut budget: ResMut<WaterBudget>,
+) {
+    debug.fn_order.push("snowfall_system".to_string());
+    let glaciers = &ecosystem_attributes.glaciers;
+
+    for (mut elevation, mut snowpack, weather, temperature, tile_type) in query.iter_mut() {
+        if *tile_type == TileType::Ocean || temperature.value > glaciers.snow_temperature {
+            continue;
+        }
+        let snowfall = weather
+            .precipitation
+            .value
+            .min(elevation.water.value)
+            .max(0.0);
+        elevation.water.value -= snowfall;
+        snowpack.value += snowfall;
+        budget.snowfall += snowfall;
+    }
+}
+
+/// Melts snow and then ice on warm tiles, compacts the snow that is left into ice and lets the
+/// ice flow downhill. Flowing ice grinds the bedrock beneath it into soil, and ice that reaches
+/// the coast calves into the ocean.
+pub fn glacier_system(
+    mut debug: ResMut<Epochs>,
+    mut query: Query<(
+        Entity,
+        &mut ElevationBundle,
+        &mut Snowpack,
+        &Temperature,
+        &TileType,
+        &Neighbours,
+    )>,
+    ecosystem_attributes: Res<EcosystemAttributes>,
+    mut budget: ResMut<WaterBudget>,
+    mut sediment_budget: ResMut<SedimentBudget>,
+) {
+    debug.fn_order.push("glacier_system".to_string());
+    let glaciers = &ecosystem_attributes.glaciers;
+
+    let mut surfaces: HashMap<Entity, (f32, TileType)> = HashMap::new();
+    for (entity, mut elevation, mut snowpack, temperature, tile_type, _) in query.iter_mut() {
+        if *tile_type != TileType::Ocean {
+            // snow melts before the ice beneath it
+            let mut melt =
+                (glaciers.melt_rate * (temperature.value - glaciers.snow_temperature)).max(0.0);
+            let snowmelt = melt.min(snowpack.value);
+            snowpack.value -= snowmelt;
+            melt -= snowmelt;
+            let icemelt = melt.min(elevation.ice.value);
+            elevation.ice.value -= icemelt;
+            elevation.water.value += snowmelt + icemelt;
+            budget.meltwater += snowmelt + icemelt;
+
+            let compacted = glaciers.compaction_rate * snowpack.value;
+            snowpack.value -= compacted;
+            elevation.ice.value += compacted;
+        }
+
+        surfaces.insert(entity, (elevation.height(), *tile_type));
+    }
+
+    // every glacier sends some of its ice towards each lower neighbour, all of them worked out
+    // from the same surfaces so the order tiles are visited in doesn't matter
+    let mut inflow: HashMap<Entity, f32> = HashMap::new();
+    for (entity, mut elevation, _, _, tile_type, neighbours) in query.iter_mut() {
+        if *tile_type == TileType::Ocean || elevation.ice.value <= 0.0 {
+            continue;
+        }
+        let (surface, _) = surfaces[&entity];
+        // ice that flows per unit the neighbour lies lower
+        let flow_per_drop = glaciers.flow_rate * elevation.ice.value / neighbours.ids.len() as f32;
+        let flows: Vec<(Entity, f32, TileType)> = neighbours
+            .ids
+            .iter()
+            .filter_map(|neighbour| {
+                let (neighbour_surface, neighbour_type) = surfaces.get(neighbour)?;
+                (*neighbour_surface < surface).then(|| {
+                    (
+                        *neighbour,
+                        flow_per_drop * (surface - neighbour_surface),
+                        *neighbour_type,
+                    )
+                })
+            })
+            .collect();
+
+        // a glacier can't send more ice than it holds
+        let total: f32 = flows.iter().map(|(_, flow, _)| flow).sum();
+        let scale = match total > elevation.ice.value {
+            true => elevation.ice.value / total,
+            false => 1.0,
+        };
+        for (neighbour, flow, neighbour_type) in flows {
+            let flow = flow * scale;
+            match neighbour_type {
+                TileType::Ocean => {
+                    budget.ocean_received(flow);
+                    budget.runoff_to_ocean += flow;
+                }
+                _ => *inflow.entry(neighbour).or_default() += flow,
+            }
+        }
+        let outflow = total * scale;
+        elevation.ice.value -= outflow;
+
+        // the rock it grinds off is left behind as soil
+        let eroded = (glaciers.erosion_rate * outflow).min(elevation.bedrock.value.max(0.0));
+        elevation.bedrock.value -= eroded;
+        elevation.soil.value += eroded;
+        sediment_budget.glacial_erosion += eroded;
+    }
+
+    for (entity, mut elevation, ..) in query.iter_mut() {
+        if let Some(ice) = inflow.get(&entity) {
+            elevation.ice.value += ice;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    /// Grass tiles in a row at `temperature`, each given as its bedrock height, snow and ice
+    fn row(world: &mut World, temperature: f32, tiles: &[(f32, f32, f32)]) -> Vec<Entity> {
+        let entities: Vec<Entity> = tiles
+            .iter()
+            .map(|&(bedrock, snow, ice)| {
+                let mut elevation = ElevationBundle::from(TileType::Grass, bedrock, 0.0);
+                elevation.soil.value = 1.0;
+                elevation.water.value = 0.0;
+                elevation.ice.value = ice;
+                world
+                    .spawn((
+                        elevation,
+                        Snowpack { value: snow },
+                        Temperature { value: temperature },
+                        TileType::Grass,
+                    ))
+                    .id()
+            })
+            .collect();
+        for (index, &entity) in entities.iter().enumerate() {
+            let ids = [index.checked_sub(1), Some(index + 1)]
+                .into_iter()
+                .flatten()
+                .filter_map(|neighbour| entities.get(neighbour).copied())
+                .collect();
+            world.entity_mut(entity).insert(Neighbours { ids });
+        }
+        world.insert_resource(Epochs::default());
+        world.insert_resource(EcosystemAttributes::from(&Config::defaults()));
+        world.insert_resource(WaterBudget::default());
+        world.insert_resource(SedimentBudget::default());
+        entities
+    }
+
+    #[test]
+    fn snow_melts_before_ice() {
+        let glaciers = GlacierAttributes::default();
+        // warm enough to melt 1 this epoch
+        let temperature = glaciers.snow_temperature + 1.0 / glaciers.melt_rate;
+
+        // plenty of snow shields the ice, and what is left of it compacts as usual
+        let mut world = World::new();
+        let tile = row(&mut world, temperature, &[(5.0, 3.0, 2.0)])[0];
+        world.run_system_once(glacier_system);
+        let elevation = world.get::<ElevationBundle>(tile).unwrap();
+        let compacted = glaciers.compaction_rate * 2.0;
+        assert!((world.get::<Snowpack>(tile).unwrap().value - (2.0 - compacted)).abs() < 1e-5);
+        assert!((elevation.ice.value - (2.0 + compacted)).abs() < 1e-5);
+        assert!((elevation.water.value - 1.0).abs() < 1e-5);
+
+        // once the snow is gone the ice melts
+        let mut world = World::new();
+        let tile = row(&mut world, temperature, &[(5.0, 0.25, 2.0)])[0];
+        world.run_system_once(glacier_system);
+        let elevation = world.get::<ElevationBundle>(tile).unwrap();
+        assert_eq!(world.get::<Snowpack>(tile).unwrap().value, 0.0);
+        assert!((elevation.ice.value - 1.25).abs() < 1e-5);
+        assert!((elevation.water.value - 1.0).abs() < 1e-5);
+        assert!((world.resource::<WaterBudget>().meltwater - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn flowing_ice_grinds_bedrock_into_soil() {
+        let glaciers = GlacierAttributes::default();
+        let mut world = World::new();
+        // a glacier on the mountain flows down into the valley without melting
+        let tiles = row(
+            &mut world,
+            glaciers.snow_temperature - 10.0,
+            &[(6.0, 0.0, 2.0), (1.0, 0.0, 0.0)],
+        );
+        let rock = |world: &World| {
+            tiles
+                .iter()
+                .map(|&tile| {
+                    let elevation = world.get::<ElevationBundle>(tile).unwrap();
+                    elevation.bedrock.value + elevation.soil.value
+                })
+                .sum::<f32>()
+        };
+        let before = rock(&world);
+        world.run_system_once(glacier_system);
+
+        let mountain = world.get::<ElevationBundle>(tiles[0]).unwrap();
+        let eroded = world.resource::<SedimentBudget>().glacial_erosion;
+        assert!(eroded > 0.0);
+        assert!((mountain.bedrock.value - (6.0 - eroded)).abs() < 1e-5);
+        assert!((mountain.soil.value - (1.0 + eroded)).abs() < 1e-5);
+        assert!((rock(&world) - before).abs() < 1e-5);
+        assert!(world.get::<ElevationBundle>(tiles[1]).unwrap().ice.value > 0.0);
+    }
+}
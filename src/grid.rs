@@ -0,0 +1,565 @@
+use bevy::prelude::*;
+use hexx::Hex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::components::GeoCoordinates;
+use crate::pointy_layout;
+use crate::world::MapAttributes;
+
+/// The layout of tiles the world is built on, picked by the `map_shape` config key
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MapShape {
+    // flat hexagon of `map_radius` rings
+    #[default]
+    Hexagon,
+    // geodesic globe of 10 * subdivisions^2 + 2 tiles, 12 of which are pentagons
+    Sphere {
+        subdivisions: u16,
+    },
+    // rectangle of rows from pole to pole whose left and right edges join up
+    Cylinder {
+        width: u16,
+        height: u16,
+    },
+}
+
+impl MapShape {
+    /// Number of tiles from the centre of the map to its edge, or from a pole to the equator
+    pub fn radius(&self, map_radius: u16) -> f32 {
+        match self {
+            MapShape::Hexagon => map_radius as f32,
+            MapShape::Sphere { subdivisions } => 1.5 * *subdivisions as f32,
+            MapShape::Cylinder { height, .. } => *height as f32 / 2.0,
+        }
+    }
+}
+
+/// Every tile of the map along with how they connect.
+///
+/// Tiles are keyed by `Hex`. On a sphere the key is only an identifier, `Hex::new(index, 0)`.
+/// On a cylinder the hexes are laid out in rows ("odd-r" offset) and neighbours wrap across the
+/// left and right edges.
+#[derive(Debug, Clone, Resource)]
+pub struct Grid {
+    shape: MapShape,
+    map_radius: u16,
+    tiles: Vec<Hex>,
+    neighbours: HashMap<Hex, Vec<Hex>>,
+    // tile centres, measured so that neighbours are about one unit apart
+    positions: HashMap<Hex, Vec3>,
+    // distance from the centre of a sphere to its tiles in the same units
+    sphere_radius: f32,
+}
+
+impl Grid {
+    pub fn new(map_attributes: &MapAttributes) -> Self {
+        match map_attributes.map_shape {
+            MapShape::Hexagon => Self::hexagon(map_attributes.map_radius),
+            MapShape::Sphere { subdivisions } => Self::sphere(subdivisions),
+            MapShape::Cylinder { width, height } => Self::cylinder(width, height),
+        }
+    }
+
+    fn hexagon(map_radius: u16) -> Self {
+        // use hexx lib to generate hexagon shaped map of hexagons
+        let tiles: Vec<Hex> = hexx::shapes::hexagon(Hex::ZERO, map_radius as u32).collect();
+        let on_map: HashSet<Hex> = tiles.iter().cloned().collect();
+
+        let neighbours = tiles
+            .iter()
+            .map(|hex| {
+                let adjacent = hex
+                    .ring(1)
+                    .filter(|neighbour| on_map.contains(neighbour))
+                    .collect();
+                (*hex, adjacent)
+            })
+            .collect();
+        Grid {
+            shape: MapShape::Hexagon,
+            map_radius,
+            positions: flat_positions(&tiles),
+            tiles,
+            neighbours,
+            sphere_radius: 0.0,
+        }
+    }
+
+    fn cylinder(width: u16, height: u16) -> Self {
+        let (width, height) = (width as i32, height as i32);
+        let tiles: Vec<Hex> = (-height / 2..height - height / 2)
+            .flat_map(|row| {
+                (-width / 2..width - width / 2).map(move |column| from_offset(column, row))
+            })
+            .collect();
+
+        let neighbours = tiles
+            .iter()
+            .map(|hex| {
+                let adjacent = hex
+                    .ring(1)
+                    .filter_map(|neighbour| {
+                        let (column, row) = to_offset(neighbour);
+                        if row < -height / 2 || row >= height - height / 2 {
+                            return None;
+                        }
+                        let column = (column + width / 2).rem_euclid(width) - width / 2;
+                        Some(from_offset(column, row))
+                    })
+                    .collect();
+                (*hex, adjacent)
+            })
+            .collect();
+
+        Grid {
+            shape: MapShape::Cylinder {
+                width: width as u16,
+                height: height as u16,
+            },
+            map_radius: (height / 2) as u16,
+            positions: flat_positions(&tiles),
+            tiles,
+            neighbours,
+            sphere_radius: 0.0,
+        }
+    }
+
+    /// Builds a Goldberg polyhedron by subdividing the faces of an icosahedron. The vertices of
+    /// the subdivided mesh are the tiles and its edges connect neighbours.
+    fn sphere(subdivisions: u16) -> Self {
+        let n = subdivisions as u32;
+        let corners = icosahedron();
+        let faces = icosahedron_faces(&corners);
+
+        // points on shared edges and corners are keyed by their weights so each is made once
+        let mut index_of: HashMap<Vec<(usize, u32)>, usize> = HashMap::new();
+        let mut directions: Vec<Vec3> = vec![];
+        let mut edges: Vec<BTreeSet<usize>> = vec![];
+
+        for [a, b, c] in faces {
+            let mut point = |i: u32, j: u32| {
+                let mut key: Vec<(usize, u32)> = [(a, n - i - j), (b, i), (c, j)]
+                    .into_iter()
+                    .filter(|(_, weight)| *weight > 0)
+                    .collect();
+                key.sort();
+                *index_of.entry(key).or_insert_with(|| {
+                    let direction = (corners[a] * (n - i - j) as f32
+                        + corners[b] * i as f32
+                        + corners[c] * j as f32)
+                        .normalize();
+                    directions.push(direction);
+                    edges.push(BTreeSet::new());
+                    directions.len() - 1
+                })
+            };
+
+            let mut links = vec![];
+            for i in 0..=n {
+                for j in 0..=(n - i) {
+                    let here = point(i, j);
+                    if i + j < n {
+                        let next_i = point(i + 1, j);
+                        let next_j = point(i, j + 1);
+                        links.extend([(here, next_i), (here, next_j), (next_i, next_j)]);
+                    }
+                }
+            }
+            for (from, to) in links {
+                edges[from].insert(to);
+                edges[to].insert(from);
+            }
+        }
+
+        let edge_count: usize = edges.iter().map(|adjacent| adjacent.len()).sum();
+        let mean_edge: f32 = edges
+            .iter()
+            .enumerate()
+            .flat_map(|(from, adjacent)| {
+                adjacent
+                    .iter()
+                    .map(move |to| (from, *to))
+                    .collect::<Vec<_>>()
+            })
+            .map(|(from, to)| directions[from].distance(directions[to]))
+            .sum::<f32>()
+            / edge_count as f32;
+        let sphere_radius = 1.0 / mean_edge;
+
+        let key = |index: usize| Hex::new(index as i32, 0);
+        let tiles: Vec<Hex> = (0..directions.len()).map(key).collect();
+        let neighbours = edges
+            .iter()
+            .enumerate()
+            .map(|(index, adjacent)| {
+                // go around each tile in order so that neighbour lists are stable
+                let normal = directions[index];
+                let (east, north) = tangent_basis(normal);
+                let mut adjacent: Vec<usize> = adjacent.iter().cloned().collect();
+                adjacent.sort_by(|x, y| {
+                    let angle = |other: usize| {
+                        let offset = directions[other] - normal;
+                        offset.dot(north).atan2(offset.dot(east))
+                    };
+                    angle(*x).total_cmp(&angle(*y))
+                });
+                (key(index), adjacent.into_iter().map(key).collect())
+            })
+            .collect();
+        let positions = directions
+            .iter()
+            .enumerate()
+            .map(|(index, direction)| (key(index), *direction * sphere_radius))
+            .collect();
+
+        Grid {
+            shape: MapShape::Sphere { subdivisions },
+            map_radius: 0,
+            tiles,
+            neighbours,
+            positions,
+            sphere_radius,
+        }
+    }
+
+    pub fn shape(&self) -> &MapShape {
+        &self.shape
+    }
+
+    pub fn tiles(&self) -> &[Hex] {
+        &self.tiles
+    }
+
+    pub fn neighbours(&self, hex: Hex) -> &[Hex] {
+        &self.neighbours[&hex]
+    }
+
+    /// Tiles grouped by how many steps they are from `centre`, up to `max_distance`
+    pub fn rings(&self, centre: Hex, max_distance: u16) -> Vec<Vec<Hex>> {
+        match self.shape {
+            // keeps the hexx ring order that world generation has always used
+            MapShape::Hexagon => (0..=max_distance as u32)
+                .map(|distance| match distance {
+                    0 => vec![centre],
+                    _ => centre
+                        .ring(distance)
+                        .filter(|hex| self.neighbours.contains_key(hex))
+                        .collect(),
+                })
+                .collect(),
+            MapShape::Sphere { .. } | MapShape::Cylinder { .. } => {
+                let mut rings = vec![vec![centre]];
+                let mut seen: HashSet<Hex> = HashSet::from([centre]);
+                while rings.len() <= max_distance as usize {
+                    let mut next = vec![];
+                    for hex in rings.last().unwrap() {
+                        for neighbour in self.neighbours(*hex) {
+                            if seen.insert(*neighbour) {
+                                next.push(*neighbour);
+                            }
+                        }
+                    }
+                    if next.is_empty() {
+                        break;
+                    }
+                    rings.push(next);
+                }
+                rings
+            }
+        }
+    }
+
+    /// Centre of a tile, with neighbouring tiles about one unit apart
+    pub fn position(&self, hex: Hex) -> Vec3 {
+        self.positions[&hex]
+    }
+
+    /// Offset from one tile to another, taking the short way across the seam of a cylinder
+    pub fn direction(&self, from: Hex, to: Hex) -> Vec3 {
+        let mut offset = self.position(to) - self.position(from);
+        if let MapShape::Cylinder { width, .. } = self.shape {
+            let width = width as f32;
+            offset.x = (offset.x + width / 2.0).rem_euclid(width) - width / 2.0;
+        }
+        offset
+    }
+
+    /// Unit vector from a tile towards another, split into how far it points east and north
+    pub fn heading(&self, from: Hex, to: Hex) -> Vec2 {
+        let offset = self.direction(from, to);
+        match self.shape {
+            // rows run from north to south
+            MapShape::Hexagon | MapShape::Cylinder { .. } => Vec2::new(offset.x, -offset.y),
+            MapShape::Sphere { .. } => {
+                let up = self.position(from).normalize();
+                // east has no direction at the poles, where any frame along the surface will do
+                let east = up
+                    .cross(Vec3::Y)
+                    .try_normalize()
+                    .unwrap_or_else(|| tangent_basis(up).0);
+                let north = east.cross(up);
+                Vec2::new(offset.dot(east), offset.dot(north))
+            }
+        }
+        .normalize_or_zero()
+    }
+
+    /// Centre of a tile with a cylinder rolled up so that its seam closes, flat maps stay flat
+    pub fn surface_position(&self, hex: Hex) -> Vec3 {
+        let position = self.position(hex);
+        match self.shape {
+            MapShape::Cylinder { width, .. } => {
+                let radius = width as f32 / std::f32::consts::TAU;
+                let angle = position.x / radius;
+                Vec3::new(radius * angle.cos(), position.y, radius * angle.sin())
+            }
+            _ => position,
+        }
+    }
+
+    /// How far a tile is towards the edge of a flat map (0 - 1), always 0 on a sphere
+    pub fn edge_fraction(&self, hex: Hex) -> f32 {
+        match self.shape {
+            MapShape::Hexagon => {
+                hex.unsigned_distance_to(Hex::ZERO) as f32 / self.map_radius.max(1) as f32
+            }
+            MapShape::Sphere { .. } => 0.0,
+            // only the poles are edges
+            MapShape::Cylinder { height, .. } => {
+                (hex.y as f32 + 0.5 - (height % 2) as f32 / 2.0).abs() / (height as f32 / 2.0)
+            }
+        }
+    }
+
+    /// Width of the rendered map before it repeats, if it wraps around
+    pub fn wrap_width(&self, hex_size: f32) -> Option<f32> {
+        match self.shape {
+            // columns in a pointy layout are sqrt(3) * hex_size apart
+            MapShape::Cylinder { width, .. } => Some(width as f32 * 3.0_f32.sqrt() * hex_size),
+            _ => None,
+        }
+    }
+
+    /// Latitude and longitude of a tile in degrees. Flat maps span pole to pole from top to
+    /// bottom and half way around the world from left to right.
+    pub fn geo_coordinates(&self, hex: Hex) -> GeoCoordinates {
+        match self.shape {
+            MapShape::Hexagon => {
+                let map_radius = self.map_radius.max(1) as f32;
+                GeoCoordinates {
+                    latitude: -hex.y as f32 / map_radius * 90.0,
+                    longitude: self.position(hex).x / map_radius * 90.0,
+                }
+            }
+            MapShape::Sphere { .. } => {
+                let direction = self.position(hex).normalize();
+                GeoCoordinates {
+                    latitude: direction.y.clamp(-1.0, 1.0).asin().to_degrees(),
+                    longitude: direction.z.atan2(direction.x).to_degrees(),
+                }
+            }
+            MapShape::Cylinder { width, height } => {
+                let row = hex.y + height as i32 / 2;
+                GeoCoordinates {
+                    latitude: 90.0 - (row as f32 + 0.5) / height as f32 * 180.0,
+                    longitude: self.position(hex).x / width as f32 * 360.0,
+                }
+            }
+        }
+    }
+
+    /// Where a tile's scene goes: laid flat for a hexagon, or standing on the surface of a globe
+    pub fn tile_transform(&self, hex: Hex, hex_size: f32) -> Transform {
+        let scale = Vec3::splat(2.0);
+        match self.shape {
+            MapShape::Hexagon | MapShape::Cylinder { .. } => {
+                let pos = pointy_layout(hex_size).hex_to_world_pos(hex);
+                Transform::from_xyz(pos.x, 0.0, pos.y).with_scale(scale)
+            }
+            MapShape::Sphere { .. } => {
+                // neighbouring hexes in a pointy layout are sqrt(3) * hex_size apart
+                let up = self.position(hex).normalize();
+                Transform::from_translation(self.position(hex) * 3.0_f32.sqrt() * hex_size)
+                    .with_rotation(Quat::from_rotation_arc(Vec3::Y, up))
+                    .with_scale(scale)
+            }
+        }
+    }
+
+    /// Radius of the rendered globe, 0 for flat maps
+    pub fn globe_radius(&self, hex_size: f32) -> f32 {
+        self.sphere_radius * 3.0_f32.sqrt() * hex_size
+    }
+}
+
+/// Centres of hexes in a pointy layout with neighbours one unit apart
+fn flat_positions(tiles: &[Hex]) -> HashMap<Hex, Vec3> {
+    tiles
+        .iter()
+        .map(|hex| {
+            let position = Vec3::new(
+                hex.x as f32 + hex.y as f32 / 2.0,
+                hex.y as f32 * 3.0_f32.sqrt() / 2.0,
+                0.0,
+            );
+            (*hex, position)
+        })
+        .collect()
+}
+
+/// Column and row of a hex in a layout where odd rows are pushed half a hex to the right
+fn to_offset(hex: Hex) -> (i32, i32) {
+    (hex.x + (hex.y - (hex.y & 1)) / 2, hex.y)
+}
+
+fn from_offset(column: i32, row: i32) -> Hex {
+    Hex::new(column - (row - (row & 1)) / 2, row)
+}
+
+/// Corners of an icosahedron with one at each pole
+fn icosahedron() -> Vec<Vec3> {
+    let ring_latitude = 0.5_f32.atan();
+    let mut corners = vec![Vec3::Y];
+    for (latitude, offset) in [(ring_latitude, 0.0), (-ring_latitude, 36.0_f32)] {
+        for step in 0..5 {
+            let longitude = (offset + 72.0 * step as f32).to_radians();
+            corners.push(Vec3::new(
+                latitude.cos() * longitude.cos(),
+                latitude.sin(),
+                latitude.cos() * longitude.sin(),
+            ));
+        }
+    }
+    corners.push(Vec3::NEG_Y);
+    corners
+}
+
+/// The 20 triangles of the icosahedron, found as the triples of corners that are all one edge apart
+fn icosahedron_faces(corners: &[Vec3]) -> Vec<[usize; 3]> {
+    let edge = corners[0].distance(corners[1]);
+    let adjacent = |a: usize, b: usize| (corners[a].distance(corners[b]) - edge).abs() < 1e-3;
+
+    let mut faces = vec![];
+    for a in 0..corners.len() {
+        for b in (a + 1)..corners.len() {
+            for c in (b + 1)..corners.len() {
+                if adjacent(a, b) && adjacent(b, c) && adjacent(a, c) {
+                    faces.push([a, b, c]);
+                }
+            }
+        }
+    }
+    faces
+}
+
+/// Two directions along the surface of a sphere at the given normal
+fn tangent_basis(normal: Vec3) -> (Vec3, Vec3) {
+    let reference = if normal.y.abs() < 0.9 {
+        Vec3::Y
+    } else {
+        Vec3::X
+    };
+    let east = reference.cross(normal).normalize();
+    let north = normal.cross(east);
+    (east, north)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every neighbour is another tile on the map, listed once, that lists the tile back
+    fn assert_symmetric(grid: &Grid) {
+        for hex in grid.tiles() {
+            let neighbours = grid.neighbours(*hex);
+            let unique: HashSet<&Hex> = neighbours.iter().collect();
+            assert_eq!(
+                unique.len(),
+                neighbours.len(),
+                "{:?} repeats a neighbour",
+                hex
+            );
+            for neighbour in neighbours {
+                assert_ne!(neighbour, hex);
+                assert!(
+                    grid.neighbours(*neighbour).contains(hex),
+                    "{:?} is next to {:?} but not the other way around",
+                    hex,
+                    neighbour
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_is_a_goldberg_polyhedron() {
+        for subdivisions in [1, 2, 5] {
+            let grid = Grid::sphere(subdivisions);
+            let n = subdivisions as usize;
+            assert_eq!(grid.tiles().len(), 10 * n * n + 2);
+
+            let pentagons = grid
+                .tiles()
+                .iter()
+                .filter(|hex| grid.neighbours(**hex).len() == 5)
+                .count();
+            let hexagons = grid
+                .tiles()
+                .iter()
+                .filter(|hex| grid.neighbours(**hex).len() == 6)
+                .count();
+            assert_eq!(pentagons, 12);
+            assert_eq!(hexagons, grid.tiles().len() - 12);
+
+            assert_symmetric(&grid);
+        }
+    }
+
+    #[test]
+    fn cylinder_wraps_around_its_seam() {
+        for (width, height) in [(3, 4), (8, 6), (9, 5)] {
+            let grid = Grid::cylinder(width, height);
+            assert_eq!(grid.tiles().len(), width as usize * height as usize);
+
+            // only the rows at the poles miss the neighbours above or below them
+            let (top, bottom) = (-(height as i32) / 2, height as i32 - height as i32 / 2 - 1);
+            for hex in grid.tiles() {
+                let expected = if hex.y == top || hex.y == bottom {
+                    4
+                } else {
+                    6
+                };
+                assert_eq!(grid.neighbours(*hex).len(), expected, "{:?}", hex);
+            }
+
+            // the first and last tile of a row are neighbours across the seam
+            let (left, right) = (-(width as i32) / 2, width as i32 - width as i32 / 2 - 1);
+            for row in top..=bottom {
+                assert!(grid
+                    .neighbours(from_offset(left, row))
+                    .contains(&from_offset(right, row)));
+            }
+
+            assert_symmetric(&grid);
+        }
+    }
+
+    #[test]
+    fn every_sphere_tile_has_a_heading_to_its_neighbours() {
+        let grid = Grid::sphere(4);
+        for hex in grid.tiles() {
+            for neighbour in grid.neighbours(*hex) {
+                let heading = grid.heading(*hex, *neighbour);
+                assert!(
+                    (heading.length() - 1.0).abs() < 1e-4,
+                    "heading from {:?} to {:?} is {:?}",
+                    hex,
+                    neighbour,
+                    heading
+                );
+            }
+        }
+    }
+}
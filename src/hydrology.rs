@@ -0,0 +1,566 @@
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+
+use crate::components::{
+    Aquifer, DebugWeatherBundle, ElevationBundle, HexCoordinates, Lake, LowerNeighbours,
+    Neighbours, River,
+};
+use crate::terrain::TileType;
+use crate::utils::get_lowest_neighbour;
+use crate::water_budget::WaterBudget;
+use crate::world::ErosionAttributes;
+use crate::{Epochs, HexToEntity};
+
+///////////////////////////////// Flow Routing /////////////////////////////////////////
+
+/// How overflow is shared between a tile's lower neighbours, picked by the `flow_routing` config key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FlowRouting {
+    // all of it goes to the lowest neighbour, ties are broken at random
+    #[default]
+    SteepestDescent,
+    // every lower neighbour gets a share in proportion to its height drop raised to `exponent`,
+    // so higher exponents send more of it down the steepest slope
+    MultipleFlow {
+        exponent: f32,
+    },
+}
+
+impl FlowRouting {
+    /// Share of the overflow of a tile at `height` that each of its lower neighbours receives
+    pub fn split<R: Rng + ?Sized>(
+        &self,
+        height: f32,
+        lower_neighbours: &LowerNeighbours,
+        rng: &mut R,
+    ) -> Vec<(Entity, f32)> {
+        match self {
+            FlowRouting::SteepestDescent => {
+                vec![(get_lowest_neighbour(lower_neighbours, rng), 1.0)]
+            }
+            FlowRouting::MultipleFlow { exponent } => {
+                let weights: Vec<(Entity, f32)> = lower_neighbours
+                    .ids
+                    .iter()
+                    .map(|(id, neighbour_height)| {
+                        (*id, (height - neighbour_height).max(0.0).powf(*exponent))
+                    })
+                    .collect();
+                let total: f32 = weights.iter().map(|(_, weight)| weight).sum();
+
+                // drops too small to tell apart are shared evenly
+                if total <= 0.0 {
+                    let share = 1.0 / weights.len() as f32;
+                    return weights.into_iter().map(|(id, _)| (id, share)).collect();
+                }
+                weights
+                    .into_iter()
+                    .map(|(id, weight)| (id, weight / total))
+                    .collect()
+            }
+        }
+    }
+}
+
+///////////////////////////////// Basins /////////////////////////////////////////
+
+/// Result of flooding the terrain inwards from its outlets
+pub struct PriorityFlood {
+    // lowest water level at which each tile drains to an outlet
+    pub fill: Vec<f32>,
+    // for tiles in a depression, the rim tile the depression spills over
+    pub spill: Vec<Option<usize>>,
+}
+
+/// A tile waiting to be flooded, lowest level first and then in tile order
+struct FloodCell {
+    level: f32,
+    index: usize,
+}
+
+impl PartialEq for FloodCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FloodCell {}
+
+impl PartialOrd for FloodCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FloodCell {
+    // reversed so that `BinaryHeap` pops the lowest cell
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .level
+            .total_cmp(&self.level)
+            .then(other.index.cmp(&self.index))
+    }
+}
+
+/// Priority-flood (Barnes et al. 2014): starting from the outlets, always flood the lowest tile
+/// on the edge of the flooded area next. A tile that is lower than the tile it was reached from
+/// sits in a depression, which has to fill up to that level before it can drain.
+///
+/// When there are no outlets the lowest tile is used, so the whole world drains towards it.
+pub fn priority_flood(
+    ground: &[f32],
+    outlets: &[bool],
+    neighbours: &[Vec<usize>],
+) -> PriorityFlood {
+    let mut fill = ground.to_vec();
+    let mut spill = vec![None; ground.len()];
+    let mut flooded = vec![false; ground.len()];
+    let mut open = BinaryHeap::new();
+
+    let mut seeds: Vec<usize> = (0..ground.len()).filter(|&index| outlets[index]).collect();
+    if seeds.is_empty() {
+        seeds.extend((0..ground.len()).min_by(|&a, &b| ground[a].total_cmp(&ground[b])));
+    }
+    for index in seeds {
+        flooded[index] = true;
+        open.push(FloodCell {
+            level: ground[index],
+            index,
+        });
+    }
+
+    while let Some(FloodCell { level, index }) = open.pop() {
+        for &neighbour in &neighbours[index] {
+            if flooded[neighbour] {
+                continue;
+            }
+            flooded[neighbour] = true;
+            if ground[neighbour] < level {
+                fill[neighbour] = level;
+                // a depression keeps the rim it was first reached from
+                spill[neighbour] = Some(spill[index].unwrap_or(index));
+            }
+            open.push(FloodCell {
+                level: fill[neighbour],
+                index: neighbour,
+            });
+        }
+    }
+
+    PriorityFlood { fill, spill }
+}
+
+/// Level a lake settles at when `water` is spread over tiles with the given ground heights,
+/// along with what is left over once it reaches `spill_level`
+pub fn lake_level(ground: &[f32], water: f32, spill_level: f32) -> (f32, f32) {
+    let capacity: f32 = ground.iter().map(|height| spill_level - height).sum();
+    if water >= capacity {
+        return (spill_level, water - capacity);
+    }
+
+    let mut sorted = ground.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let mut flooded_ground = 0.0;
+    for (count, height) in sorted.iter().enumerate() {
+        flooded_ground += height;
+        let level = (water + flooded_ground) / (count + 1) as f32;
+        if sorted.get(count + 1).is_none_or(|next| level <= *next) {
+            return (level, 0.0);
+        }
+    }
+    (spill_level, 0.0)
+}
+
+/// Pools the surface water of every depression into a lake, up to the point where it spills
+/// over its rim, and sends the rest over the rim
+pub fn fill_basins_system(
+    mut debug: ResMut<Epochs>,
+    mut commands: Commands,
+    mut query: Query<(
+        Entity,
+        &mut ElevationBundle,
+        &TileType,
+        &HexCoordinates,
+        &Neighbours,
+    )>,
+    lakes: Query<Entity, With<Lake>>,
+    mut budget: ResMut<WaterBudget>,
+) {
+    debug.fn_order.push("fill_basins_system".to_string());
+
+    let entities: Vec<Entity> = query.iter().map(|(entity, ..)| entity).collect();
+    let index_of: HashMap<Entity, usize> = entities
+        .iter()
+        .enumerate()
+        .map(|(index, entity)| (*entity, index))
+        .collect();
+    let mut ground = vec![];
+    let mut outlets = vec![];
+    let mut neighbours = vec![];
+    for (_entity, elevation, tile_type, _hex, tile_neighbours) in query.iter() {
+        // glaciers dam lakes like the ground does
+        ground.push(elevation.bedrock.value + elevation.soil.value + elevation.ice.value);
+        // water that reaches the sea is gone from the land
+        outlets.push(*tile_type == TileType::Ocean);
+        neighbours.push(
+            tile_neighbours
+                .ids
+                .iter()
+                .filter_map(|id| index_of.get(id).copied())
+                .collect::<Vec<usize>>(),
+        );
+    }
+
+    let flood = priority_flood(&ground, &outlets, &neighbours);
+
+    // depressions that touch each other fill to the same level, so they form one lake
+    let mut basin_of = vec![None; entities.len()];
+    let mut basins: Vec<Vec<usize>> = vec![];
+    for start in 0..entities.len() {
+        if flood.spill[start].is_none() || basin_of[start].is_some() {
+            continue;
+        }
+        let mut basin = vec![];
+        let mut queue = VecDeque::from([start]);
+        basin_of[start] = Some(basins.len());
+        while let Some(index) = queue.pop_front() {
+            basin.push(index);
+            for &neighbour in &neighbours[index] {
+                if flood.spill[neighbour].is_some() && basin_of[neighbour].is_none() {
+                    basin_of[neighbour] = Some(basins.len());
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        basins.push(basin);
+    }
+
+    let mut new_lakes: BTreeMap<usize, Lake> = BTreeMap::new();
+    for basin in &basins {
+        let spill = flood.spill[basin[0]].expect("basin tiles are in a depression");
+        let spill_level = flood.fill[basin[0]];
+
+        // surface water is pooled, water held in the soil stays where it is
+        let mut surface_water = 0.0;
+        for &index in basin {
+            let (_, elevation, ..) = query.get(entities[index]).unwrap();
+            surface_water += (elevation.water.value - elevation.soil.value).max(0.0);
+        }
+        let basin_ground: Vec<f32> = basin.iter().map(|&index| ground[index]).collect();
+        let (level, excess) = lake_level(&basin_ground, surface_water, spill_level);
+
+        let (_, _, _, spill_hex, _) = query.get(entities[spill]).unwrap();
+        let spill_hex = spill_hex.0;
+        for &index in basin {
+            let (_, mut elevation, ..) = query.get_mut(entities[index]).unwrap();
+            let depth = (level - ground[index]).max(0.0);
+            elevation.water.value = elevation.water.value.min(elevation.soil.value) + depth;
+            if depth > 0.0 {
+                new_lakes.insert(
+                    index,
+                    Lake {
+                        level,
+                        spill: spill_hex,
+                    },
+                );
+            }
+        }
+
+        // a full lake overflows onto its rim, and from there downhill
+        if excess > 0.0 {
+            let (_, mut elevation, tile_type, ..) = query.get_mut(entities[spill]).unwrap();
+            match tile_type {
+                TileType::Ocean => {
+                    budget.runoff_to_ocean += excess;
+                    budget.ocean_received(excess);
+                }
+                _ => elevation.water.value += excess,
+            }
+        }
+    }
+
+    for entity in lakes.iter() {
+        if !new_lakes.contains_key(&index_of[&entity]) {
+            commands.entity(entity).remove::<Lake>();
+        }
+    }
+    for (index, lake) in new_lakes {
+        commands.entity(entities[index]).insert(lake);
+    }
+}
+
+///////////////////////////////// Rivers /////////////////////////////////////////
+
+/// Adds up the rain falling and the springs rising upstream of every tile along the last
+/// overflow, and marks the tiles it reaches the river threshold on as rivers
+pub fn river_network_system(
+    mut debug: ResMut<Epochs>,
+    mut commands: Commands,
+    query: Query<(Entity, &DebugWeatherBundle, &Aquifer, &TileType)>,
+    lakes: Query<&Lake>,
+    rivers: Query<Entity, With<River>>,
+    erosion_attributes: Res<ErosionAttributes>,
+    hex_to_entity: Res<HexToEntity>,
+) {
+    debug.fn_order.push("river_network_system".to_string());
+
+    let entities: Vec<Entity> = query.iter().map(|(entity, ..)| entity).collect();
+    let index_of: HashMap<Entity, usize> = entities
+        .iter()
+        .enumerate()
+        .map(|(index, entity)| (*entity, index))
+        .collect();
+
+    let mut discharge = vec![];
+    let mut downstream: Vec<Vec<(usize, f32)>> = vec![];
+    for (entity, weather, aquifer, tile_type) in query.iter() {
+        // springs feed rivers as well as the rain
+        discharge.push(match tile_type {
+            TileType::Ocean => 0.0,
+            _ => weather.precipitation.value + aquifer.spring,
+        });
+
+        // a lake passes everything that drains into it on over its rim
+        if let Ok(lake) = lakes.get(entity) {
+            downstream.push(
+                hex_to_entity
+                    .0
+                    .get(&lake.spill)
+                    .map(|spill| vec![(index_of[spill], 1.0)])
+                    .unwrap_or_default(),
+            );
+            continue;
+        }
+        let total: f32 = weather
+            .overflow_split
+            .iter()
+            .map(|(_, outflow)| outflow.water)
+            .sum();
+        downstream.push(match total > 0.0 {
+            true => weather
+                .overflow_split
+                .iter()
+                .map(|(neighbour, outflow)| (index_of[neighbour], outflow.water / total))
+                .collect(),
+            false => vec![],
+        });
+    }
+
+    // visit tiles after everything upstream of them, water runs downhill so there are no loops
+    // apart from the odd lake that spills back into itself, which are left where they are
+    let mut upstream_count = vec![0; entities.len()];
+    for receivers in &downstream {
+        for (receiver, _) in receivers {
+            upstream_count[*receiver] += 1;
+        }
+    }
+    let mut ready: VecDeque<usize> = (0..entities.len())
+        .filter(|&index| upstream_count[index] == 0)
+        .collect();
+    while let Some(index) = ready.pop_front() {
+        for &(receiver, share) in &downstream[index] {
+            discharge[receiver] += discharge[index] * share;
+            upstream_count[receiver] -= 1;
+            if upstream_count[receiver] == 0 {
+                ready.push_back(receiver);
+            }
+        }
+    }
+
+    let mut new_rivers: BTreeMap<usize, River> = BTreeMap::new();
+    for (index, (entity, .., tile_type)) in query.iter().enumerate() {
+        if *tile_type == TileType::Ocean
+            || lakes.contains(entity)
+            || discharge[index] < erosion_attributes.river_threshold
+        {
+            continue;
+        }
+        // the first of the largest shares, `max_by` keeps the last one it finds
+        let main_channel = downstream[index]
+            .iter()
+            .rev()
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((receiver, _)) = main_channel {
+            new_rivers.insert(
+                index,
+                River {
+                    discharge: discharge[index],
+                    downstream: entities[*receiver],
+                },
+            );
+        }
+    }
+
+    for entity in rivers.iter() {
+        if !new_rivers.contains_key(&index_of[&entity]) {
+            commands.entity(entity).remove::<River>();
+        }
+    }
+    for (index, river) in new_rivers {
+        commands.entity(entities[index]).insert(river);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    /// Lower neighbours of a tile at height 10 that drop 1, 2 and 4 below it
+    fn slopes() -> LowerNeighbours {
+        LowerNeighbours {
+            ids: vec![
+                (Entity::from_raw(1), 9.0),
+                (Entity::from_raw(2), 8.0),
+                (Entity::from_raw(3), 6.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn multiple_flow_shares_overflow_by_drop() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let exponent = 1.5;
+        let split = FlowRouting::MultipleFlow { exponent }.split(10.0, &slopes(), &mut rng);
+        assert_eq!(split.len(), 3);
+        let total: f32 = split.iter().map(|(_, share)| share).sum();
+        assert!((total - 1.0).abs() < 1e-6);
+        // each share is its drop raised to the exponent over the sum of them all
+        let weights = [1.0f32, 2.0, 4.0].map(|drop: f32| drop.powf(exponent));
+        let weight_total: f32 = weights.iter().sum();
+        for ((id, share), weight) in split.iter().zip(weights) {
+            assert!((share - weight / weight_total).abs() < 1e-6, "{:?}", id);
+        }
+    }
+
+    #[test]
+    fn higher_exponents_send_more_down_the_steepest_slope() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let steepest_share =
+            |exponent| FlowRouting::MultipleFlow { exponent }.split(10.0, &slopes(), &mut rng)[2].1;
+        let shares = [0.0, 1.0, 4.0].map(steepest_share);
+        assert!((shares[0] - 1.0 / 3.0).abs() < 1e-6);
+        assert!(shares[0] < shares[1] && shares[1] < shares[2]);
+    }
+
+    #[test]
+    fn steepest_descent_sends_everything_to_the_lowest_neighbour() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let split = FlowRouting::SteepestDescent.split(10.0, &slopes(), &mut rng);
+        assert_eq!(split, vec![(Entity::from_raw(3), 1.0)]);
+    }
+
+    /// Neighbours of tiles laid out in a row
+    fn line(len: usize) -> Vec<Vec<usize>> {
+        (0..len)
+            .map(|index| {
+                [index.checked_sub(1), Some(index + 1)]
+                    .into_iter()
+                    .flatten()
+                    .filter(|&neighbour| neighbour < len)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Neighbours of tiles laid out in a square, row by row
+    fn square(side: usize) -> Vec<Vec<usize>> {
+        (0..side * side)
+            .map(|index| {
+                let (row, column) = (index / side, index % side);
+                let mut neighbours = vec![];
+                if row > 0 {
+                    neighbours.push(index - side);
+                }
+                if row + 1 < side {
+                    neighbours.push(index + side);
+                }
+                if column > 0 {
+                    neighbours.push(index - 1);
+                }
+                if column + 1 < side {
+                    neighbours.push(index + 1);
+                }
+                neighbours
+            })
+            .collect()
+    }
+
+    #[test]
+    fn terrain_without_depressions_is_left_as_it_is() {
+        let ground = [0.0, 1.0, 2.0, 3.0];
+        let flood = priority_flood(&ground, &[true, false, false, false], &line(4));
+        assert_eq!(flood.fill, ground);
+        assert_eq!(flood.spill, vec![None; 4]);
+    }
+
+    #[test]
+    fn depression_fills_up_to_its_rim() {
+        let ground = [0.0, 3.0, 1.0, 2.0, 5.0];
+        let flood = priority_flood(&ground, &[true, false, false, false, false], &line(5));
+        assert_eq!(flood.fill, vec![0.0, 3.0, 3.0, 3.0, 5.0]);
+        assert_eq!(flood.spill, vec![None, None, Some(1), Some(1), None]);
+    }
+
+    #[test]
+    fn depression_spills_over_the_lowest_point_of_its_rim() {
+        #[rustfmt::skip]
+        let ground = [
+            5.0, 4.0, 5.0,
+            3.0, 1.0, 6.0,
+            5.0, 7.0, 5.0,
+        ];
+        let outlets: Vec<bool> = (0..9).map(|index| index != 4).collect();
+        let flood = priority_flood(&ground, &outlets, &square(3));
+        assert_eq!(flood.fill[4], 3.0);
+        assert_eq!(flood.spill[4], Some(3));
+        assert!(flood
+            .spill
+            .iter()
+            .enumerate()
+            .all(|(index, spill)| index == 4 || spill.is_none()));
+    }
+
+    #[test]
+    fn adjacent_depressions_below_a_shared_rim_merge() {
+        // two pits split by a divide lower than the rim they both sit behind
+        let ground = [0.0, 5.0, 1.0, 3.0, 2.0, 6.0];
+        let outlets = [true, false, false, false, false, false];
+        let flood = priority_flood(&ground, &outlets, &line(6));
+        assert_eq!(flood.fill, vec![0.0, 5.0, 5.0, 5.0, 5.0, 6.0]);
+        assert_eq!(
+            flood.spill,
+            vec![None, None, Some(1), Some(1), Some(1), None]
+        );
+    }
+
+    #[test]
+    fn without_outlets_everything_drains_to_the_lowest_tile() {
+        let ground = [1.0, 4.0, 2.0, 5.0];
+        let flood = priority_flood(&ground, &[false; 4], &line(4));
+        assert_eq!(flood.fill, vec![1.0, 4.0, 4.0, 5.0]);
+        assert_eq!(flood.spill, vec![None, None, Some(1), None]);
+    }
+
+    #[test]
+    fn lake_floods_the_lowest_tiles_first() {
+        // only the lowest tile is covered
+        assert_eq!(lake_level(&[1.0, 0.0], 0.5, 3.0), (0.5, 0.0));
+        // both are covered, and the water levels out across them
+        assert_eq!(lake_level(&[1.0, 0.0], 3.0, 3.0), (2.0, 0.0));
+        // without water the lake sits on its lowest ground
+        assert_eq!(lake_level(&[2.0, 1.0], 0.0, 3.0), (1.0, 0.0));
+    }
+
+    #[test]
+    fn lake_spills_what_it_cannot_hold() {
+        // the depression holds 3 + 2 below its rim
+        assert_eq!(lake_level(&[0.0, 1.0], 5.0, 3.0), (3.0, 0.0));
+        assert_eq!(lake_level(&[0.0, 1.0], 6.5, 3.0), (3.0, 1.5));
+    }
+}
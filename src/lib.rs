@@ -1 +1,60 @@
+use bevy::prelude::*;
+use hexx::*;
+use std::collections::HashMap;
 
+pub mod aquifer;
+pub mod atmosphere;
+pub mod biomes;
+pub mod climate;
+pub mod components;
+pub mod config;
+pub mod glaciers;
+pub mod grid;
+pub mod hydrology;
+pub mod map_generation;
+pub mod rules;
+pub mod save;
+pub mod sediment;
+pub mod simulation;
+pub mod statistics;
+pub mod terrain;
+pub mod utils;
+pub mod vegetation;
+pub mod water_budget;
+pub mod weather_systems;
+pub mod world;
+
+pub use simulation::{TerraflowSimPlugin, World};
+
+/// Describes the orientation and tile size of a hexagon grid.
+pub fn pointy_layout(hex_size: f32) -> HexLayout {
+    HexLayout {
+        orientation: HexOrientation::Pointy,
+        hex_size: Vec2::splat(hex_size),
+        ..default()
+    }
+}
+
+///////////////////////////////// Debug Resources /////////////////////////////////////////
+///
+
+#[derive(Debug, Clone, Resource, Default)]
+pub struct Epochs {
+    pub epochs: u32,
+    pub fn_order: Vec<String>,
+    pub epochs_to_run: u32,
+}
+
+#[derive(Debug, Resource)]
+pub struct HexToEntity(pub HashMap<Hex, Entity>);
+
+////////////////////////////////////////// States /////////////////////////////////////////
+
+#[derive(Clone, Eq, PartialEq, Debug, Hash, States, Default)]
+pub enum GameStates {
+    #[default]
+    Waiting,
+    EpochStart,
+    EpochRunning,
+    EpochFinish,
+}
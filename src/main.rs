@@ -1,88 +1,115 @@
-use bevy::{math::vec4, prelude::*};
+use bevy::{app::AppExit, math::vec4, prelude::*};
+use std::path::PathBuf;
 
 use bevy_basic_camera::{CameraController, CameraControllerPlugin};
 use bevy_mod_picking::prelude::*;
 
 use bevy_egui::EguiPlugin;
 
-// use hexx::shapes;
-use hexx::*;
-
-use rand::prelude::SliceRandom;
-use std::collections::HashMap;
+use clap::Parser;
 
 mod benchmark;
-mod components;
-mod map_generation;
-mod terrain;
 mod ui;
-mod utils;
-mod weather_systems;
-mod world;
-
-use components::{
-    BedrockElevation, DebugWeatherBundle, DistancesFromVolcano, ElevationBundle, Evaporation,
-    HexCoordinates, Humidity, HumidityReceived, HumiditySent, IncomingOverflow,
-    Neighbours, Overflow, OverflowReceived, PendingHumidityRedistribution, Precipitation,
-    Temperature,
-};
 
-use ui::{terrain_callback, terrain_details, SelectedTile};
-use weather_systems::{
-    apply_humidity_redistribution, apply_vulcanism, apply_water_overflow,
-    calculate_neighbour_heights_system, evaporation_system, morph_terrain_system,
-    precipitation_system, redistribute_humidity_system, redistribute_overflow_system,
-    update_terrain_assets,
-};
-use world::{TileTypeGenerator, WorldAttributes};
+use terraflow::atmosphere::Atmosphere;
+use terraflow::components::{HexCoordinates, River};
+use terraflow::config::CONFIG_ENV_VAR;
+use terraflow::grid::{Grid, MapShape};
+use terraflow::rules::RULES_ENV_VAR;
+use terraflow::save::SaveFile;
+use terraflow::sediment::SedimentBudget;
+use terraflow::terrain::{self, TileAssets, TileType};
+use terraflow::utils::SEED_ENV_VAR;
+use terraflow::water_budget::WaterBudget;
+use terraflow::weather_systems::update_terrain_assets;
+use terraflow::world::{ErosionAttributes, MapAttributes, WorldAttributes};
+use terraflow::{Epochs, GameStates, TerraflowSimPlugin};
+
+use ui::{atmosphere_details, terrain_callback, terrain_details, SelectedTile};
 
 // number of epochs to run when pressing enter
 pub const EPOCHS_ON_ENTER: u8 = 10;
 
-/// Describes the orientation and tile size of a hexagon grid.
-pub fn pointy_layout(hex_size: f32) -> HexLayout {
-    HexLayout {
-        orientation: HexOrientation::Pointy,
-        hex_size: Vec2::splat(hex_size),
-        ..default()
-    }
-}
+// fraction of a wrapping map drawn again past each edge, so the seam is never empty on screen
+const SEAM_COPY_FRACTION: f32 = 0.25;
 
-///////////////////////////////// Debug Resources /////////////////////////////////////////
-///
+// how far above the tile centres rivers are drawn, so they show on top of the tiles
+const RIVER_HEIGHT: f32 = 0.5;
 
-#[derive(Debug, Clone, Resource, Default)]
-pub struct Epochs {
-    epochs: u16,
-    fn_order: Vec<String>,
-    epochs_to_run: u16,
-}
+////////////////////////////////////////// App /////////////////////////////////////////
 
-#[derive(Debug, Resource)]
-pub struct HexToEntity(HashMap<Hex, Entity>);
+#[derive(Debug, Parser)]
+#[command(name = "terraflow", version)]
+struct Args {
+    /// Seed for world generation and the simulation, random when omitted
+    #[arg(long, env = SEED_ENV_VAR)]
+    seed: Option<u64>,
 
-////////////////////////////////////////// App /////////////////////////////////////////
+    /// World config file layered over the built-in defaults
+    #[arg(long, env = CONFIG_ENV_VAR)]
+    config: Option<PathBuf>,
+
+    /// Terrain transition rules file used instead of the built-in rules
+    #[arg(long, env = RULES_ENV_VAR)]
+    rules: Option<PathBuf>,
 
-// Replace the u8 with unit (), since we don't need to keep track of the number of executions anymore
-#[derive(Clone, Eq, PartialEq, Debug, Hash, States, Default)]
-pub enum GameStates {
-    Waiting,
-    #[default]
-    EpochStart,
-    EpochRunning,
-    EpochFinish,
+    /// Continue a saved world instead of generating one
+    #[arg(long, conflicts_with_all = ["config", "rules", "seed"])]
+    load: Option<PathBuf>,
+
+    /// Where the world is saved on F5 and when the app closes
+    #[arg(long, default_value = "terraflow_save.json")]
+    save_path: PathBuf,
 }
 
+#[derive(Debug, Resource)]
+struct SavePath(PathBuf);
+
 fn main() {
+    let args = Args::parse();
+
+    let sim_plugin = match &args.load {
+        Some(path) => {
+            let save = SaveFile::read(path).unwrap_or_else(|error| {
+                eprintln!("Could not load {}: {}", path.display(), error);
+                std::process::exit(1);
+            });
+            println!("Loaded world at epoch {}", save.epochs);
+            TerraflowSimPlugin::load(save)
+        }
+        None => {
+            let attributes = match &args.config {
+                Some(path) => WorldAttributes::from_path(path).unwrap_or_else(|error| {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }),
+                None => WorldAttributes::load(),
+            };
+            let attributes = match &args.rules {
+                Some(path) => attributes.with_rules(path).unwrap_or_else(|error| {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }),
+                None => attributes,
+            };
+            let seed = args.seed.unwrap_or_else(rand::random);
+            println!("World seed: {} (pass --seed to reproduce)", seed);
+            TerraflowSimPlugin::generate(attributes, seed)
+        }
+    };
+
     App::new()
         .insert_resource(AmbientLight {
             brightness: 0.1,
             ..default()
         })
-        .insert_resource(Epochs::default())
         .insert_resource(SelectedTile::default())
         .insert_resource(benchmark::BenchmarkResource::default())
+        .insert_resource(SavePath(args.save_path))
         .add_plugins(DefaultPlugins)
+        .add_plugins(sim_plugin)
+        // the library waits to be told to run, the app runs its first epoch right away
+        .insert_resource(NextState(Some(GameStates::EpochStart)))
         .add_plugins(
             DefaultPickingPlugins
                 .build()
@@ -90,48 +117,36 @@ fn main() {
         )
         .add_plugins(CameraControllerPlugin)
         .add_plugins(EguiPlugin)
-        .add_systems(
-            PreStartup,
-            (setup_camera, play_tunes, load_tile_assets, setup_grid),
-        )
-        .add_state::<GameStates>()
+        .add_systems(PreStartup, (setup_camera, play_tunes, load_tile_assets))
         .add_systems(Update, bevy::window::close_on_esc)
         .add_systems(Update, start_epoch)
+        .add_systems(Update, save_on_keypress)
+        .add_systems(Last, save_on_exit)
+        .add_systems(Update, spawn_tile_scenes)
+        .add_systems(Update, update_seam_copies)
+        .add_systems(PostUpdate, wrap_camera)
         .add_systems(Update, make_pickable)
         .add_systems(Update, terrain_details)
-        // initial weather phase
+        .add_systems(Update, atmosphere_details)
+        .add_systems(Update, draw_rivers)
         .add_systems(OnEnter(GameStates::EpochStart), benchmark::start_benchmark)
-        .add_systems(OnEnter(GameStates::EpochStart), precipitation_system)
-        .add_systems(OnEnter(GameStates::EpochStart), evaporation_system)
-        .add_systems(
-            OnEnter(GameStates::EpochStart),
-            calculate_neighbour_heights_system,
-        )
-        // calculate neighbour effects
-        .add_systems(OnExit(GameStates::EpochStart), redistribute_humidity_system)
-        .add_systems(OnExit(GameStates::EpochStart), redistribute_overflow_system)
-        // apply effects on neighbours
-        .add_systems(OnEnter(GameStates::EpochRunning), apply_water_overflow)
-        .add_systems(
-            OnEnter(GameStates::EpochRunning),
-            apply_humidity_redistribution,
-        )
-        .add_systems(OnEnter(GameStates::EpochRunning), apply_vulcanism)
         // update terrain assets and map
-        .add_systems(OnExit(GameStates::EpochRunning), morph_terrain_system)
         .add_systems(OnEnter(GameStates::EpochFinish), update_terrain_assets)
-        .add_systems(OnExit(GameStates::EpochFinish), finish_epoch)
+        .add_systems(OnExit(GameStates::EpochFinish), report_epoch)
         .add_systems(OnExit(GameStates::EpochFinish), benchmark::end_benchmark)
         .run();
 }
 
-fn finish_epoch(mut epochs: ResMut<Epochs>, mut next_state: ResMut<NextState<GameStates>>) {
-    if epochs.epochs_to_run > 0 {
-        println!("{}", *epochs);
-        epochs.epochs_to_run -= 1;
-        epochs.epochs += 1;
-        next_state.set(GameStates::EpochStart);
-    }
+fn report_epoch(
+    epochs: Res<Epochs>,
+    water_budget: Res<WaterBudget>,
+    sediment_budget: Res<SedimentBudget>,
+    atmosphere: Res<Atmosphere>,
+) {
+    println!("{}", *epochs);
+    println!("{}", *water_budget);
+    println!("{}", *sediment_budget);
+    println!("{}", *atmosphere);
 }
 
 // Move the epoch forward on space bar press
@@ -142,7 +157,6 @@ fn start_epoch(
 ) {
     if keypress.just_pressed(KeyCode::Space) {
         println!("=== Epoch: {} ===\n", epochs.epochs);
-        epochs.epochs += 1;
         for epoch in epochs.fn_order.iter() {
             println!(" ---> {}", epoch);
         }
@@ -157,7 +171,30 @@ fn start_epoch(
 
         next_state.set(GameStates::EpochStart);
         epochs.fn_order.clear();
-        epochs.epochs_to_run = EPOCHS_ON_ENTER.into();
+        // the first of these epochs is started right away
+        epochs.epochs_to_run = (EPOCHS_ON_ENTER - 1).into();
+    }
+}
+
+fn save_world(world: &mut World) {
+    let save = SaveFile::capture(world);
+    let path = &world.resource::<SavePath>().0;
+    match save.write(path) {
+        Ok(()) => println!("Saved world at epoch {} to {}", save.epochs, path.display()),
+        Err(error) => eprintln!("Could not save world to {}: {}", path.display(), error),
+    }
+}
+
+// Save the world on F5 press
+fn save_on_keypress(world: &mut World) {
+    if world.resource::<Input<KeyCode>>().just_pressed(KeyCode::F5) {
+        save_world(world);
+    }
+}
+
+fn save_on_exit(world: &mut World) {
+    if !world.resource::<Events<AppExit>>().is_empty() {
+        save_world(world);
     }
 }
 
@@ -166,6 +203,73 @@ fn load_tile_assets(asset_server: Res<AssetServer>, mut commands: Commands) {
     commands.insert_resource(tile_assets);
 }
 
+/// Gives every simulated tile a scene and a click handler once it has been spawned
+fn spawn_tile_scenes(
+    mut commands: Commands,
+    query: Query<(Entity, &HexCoordinates, &TileType), Without<Handle<Scene>>>,
+    tile_assets: Res<TileAssets>,
+    map_attributes: Res<MapAttributes>,
+    grid: Res<Grid>,
+) {
+    let wrap_width = grid.wrap_width(map_attributes.hex_size);
+    for (entity, hex, tile_type) in query.iter() {
+        let scene = tile_assets.get_scene_handle(*tile_type).unwrap();
+        let transform = grid.tile_transform(hex.0, map_attributes.hex_size);
+
+        commands.entity(entity).insert((
+            SceneBundle {
+                transform,
+                scene: scene.clone(),
+                ..default()
+            },
+            On::<Pointer<Click>>::run(terrain_callback),
+        ));
+
+        // tiles near the seam are drawn again on the far side of the map
+        let Some(wrap_width) = wrap_width else {
+            continue;
+        };
+        let band = wrap_width * SEAM_COPY_FRACTION;
+        let x = transform.translation.x;
+        let offset = if x < -wrap_width / 2.0 + band {
+            wrap_width
+        } else if x > wrap_width / 2.0 - band {
+            -wrap_width
+        } else {
+            continue;
+        };
+        commands.entity(entity).with_children(|parent| {
+            parent.spawn((
+                SceneBundle {
+                    // children are scaled along with their tile
+                    transform: Transform::from_xyz(offset / transform.scale.x, 0.0, 0.0),
+                    scene: scene.clone(),
+                    ..default()
+                },
+                SeamCopy,
+            ));
+        });
+    }
+}
+
+/// Copy of a tile drawn past the edge of a wrapping map
+#[derive(Component)]
+struct SeamCopy;
+
+/// Keeps the copies of tiles near the seam showing the same scene as their tile
+fn update_seam_copies(
+    tiles: Query<(&Handle<Scene>, &Children), (Changed<Handle<Scene>>, Without<SeamCopy>)>,
+    mut copies: Query<&mut Handle<Scene>, With<SeamCopy>>,
+) {
+    for (scene, children) in tiles.iter() {
+        for child in children.iter() {
+            if let Ok(mut copy) = copies.get_mut(*child) {
+                *copy = scene.clone();
+            }
+        }
+    }
+}
+
 /// Makes everything in the scene with a mesh pickable
 fn make_pickable(
     mut commands: Commands,
@@ -195,126 +299,49 @@ const HIGHLIGHT_TINT: Highlight<StandardMaterial> = Highlight {
     })),
 };
 
-/// Hex grid setup
-fn setup_grid(asset_server: Res<AssetServer>, mut commands: Commands) {
-    let world = WorldAttributes::load();
-
-    // use hexx lib to generate hexagon shaped map of hexagons
-    let all_hexes: Vec<Hex> =
-        hexx::shapes::hexagon(Hex::ZERO, world.map.map_radius as u32).collect();
-
-    let mut rng = rand::thread_rng();
-    let volcano_hexes: Vec<Hex> = all_hexes
-        .choose_multiple(&mut rng, world.elevation.vulcanism as usize)
-        .cloned()
-        .collect();
-
-    // generate altitude and derive temperature from that
-    let altitude_map =
-        map_generation::generate_altitude_map(&world.elevation, &all_hexes, &volcano_hexes);
-    let temperature_map = map_generation::generate_temperature_map(
-        &world.temperature,
-        world.map.map_radius,
-        &altitude_map,
-    );
-
-    let mut hex_to_entity = HashMap::new();
-
-    let tile_assets = terrain::TileAssets::new(&asset_server);
-
-    // Spawn tiles
-    for hex in all_hexes.clone() {
-        let altitude = *altitude_map.get(&hex).unwrap();
-        let temperature = *temperature_map.get(&hex).unwrap();
-
-        // spawn tile based on altitude and temperature
-        let tile_type = world.spawn_tile(hex.y as f32, altitude, temperature);
-        let scene = tile_assets.get_scene_handle(tile_type).unwrap();
-        let pos = pointy_layout(world.map.hex_size).hex_to_world_pos(hex);
-        let amount_below_sea_level = (world.elevation.sea_level - altitude).max(0.0);
-
-        // create terrain entity
-        let id = commands
-            .spawn((
-                SceneBundle {
-                    transform: Transform::from_xyz(pos.x, 0.0, pos.y).with_scale(Vec3::splat(2.0)),
-                    scene: scene.clone(),
-                    ..default()
-                },
-                On::<Pointer<Click>>::run(terrain_callback),
-                ElevationBundle::from(tile_type, altitude, amount_below_sea_level),
-                Humidity::from(tile_type),
-                Temperature { value: temperature },
-                DebugWeatherBundle {
-                    evaporation: Evaporation { value: 0.0 },
-                    precipitation: Precipitation { value: 0.0 },
-                    overflow: Overflow {
-                        water: 0.0,
-                        soil: 0.0,
-                    },
-                    overflow_received: OverflowReceived {
-                        water: 0.0,
-                        soil: 0.0,
-                    },
-                    humidity_received: HumidityReceived { value: 0.0 },
-                    humidity_sent: HumiditySent { value: 0.0 },
-                },
-                HexCoordinates(hex),
-                Neighbours { ids: vec![] }, // populate once all entities are spawned
-                PendingHumidityRedistribution { value: 0.0 },
-                IncomingOverflow {
-                    water: 0.0,
-                    soil: 0.0,
-                },
-                tile_type,
-            ))
-            .id();
-
-        hex_to_entity.insert(hex, id);
-    }
-
-    let distances_to_volcanoes =
-        map_generation::get_distances_from_volcanos(&world.elevation, &volcano_hexes);
-
-    // Populate `Neighbours` component for each entity
-    for hex in all_hexes {
-        let entity_id = hex_to_entity[&hex];
-        let neighbour_hexes = hex.ring(1);
-        let neighbour_ids = neighbour_hexes
-            .into_iter()
-            .filter_map(|neighbour_hex| hex_to_entity.get(&neighbour_hex))
-            .cloned()
-            .collect::<Vec<Entity>>();
-
-        commands
-            .entity(entity_id)
-            .insert(Neighbours { ids: neighbour_ids });
-
-        match distances_to_volcanoes.get(&hex) {
-            Some(distances) => {
-                commands.entity(entity_id).insert(DistancesFromVolcano(distances.to_vec()));
-            }
-            None => continue,
+/// Draws every river from the centre of its tile to the centre of the tile it flows into,
+/// brighter the more water it carries
+fn draw_rivers(
+    mut gizmos: Gizmos,
+    rivers: Query<(&HexCoordinates, &River)>,
+    tiles: Query<&HexCoordinates>,
+    grid: Res<Grid>,
+    map_attributes: Res<MapAttributes>,
+    erosion_attributes: Res<ErosionAttributes>,
+) {
+    // neighbouring centres are sqrt(3) * hex_size apart, so anything longer crosses the seam
+    let longest = 2.0 * 3.0_f32.sqrt() * map_attributes.hex_size;
+    for (hex, river) in rivers.iter() {
+        let Ok(downstream_hex) = tiles.get(river.downstream) else {
+            continue;
+        };
+        let from = grid.tile_transform(hex.0, map_attributes.hex_size);
+        let to = grid.tile_transform(downstream_hex.0, map_attributes.hex_size);
+        let start = from.translation + from.up() * RIVER_HEIGHT;
+        let end = to.translation + to.up() * RIVER_HEIGHT;
+        if start.distance(end) > longest {
+            continue;
         }
-    }
-
-    // TODO: hex_to_entity isn't currently used
-    commands.insert_resource(HexToEntity(hex_to_entity.clone()));
-    commands.insert_resource(tile_assets);
 
-    // World Attributes
-    commands.insert_resource(world.elevation); // ElevationAttributes
-    commands.insert_resource(world.erosion); // ErosionAttributes
-    commands.insert_resource(world.ecosystem); // EcosystemAttributes
-    commands.insert_resource(world.temperature); // TemperatureAttributes
-    commands.insert_resource(world.map); // MapAttributes
+        // 0 at the river threshold, approaching 1 for the largest rivers
+        let strength = 1.0 - erosion_attributes.river_threshold / river.discharge;
+        gizmos.line(start, end, Color::rgb(0.1, 0.3 + 0.5 * strength, 1.0));
+    }
 }
 
 ////////////////////// CAMERA MOVEMENT //////////////////////
 
 // 3D Orthogrpahic camera setup
-fn setup_camera(mut commands: Commands) {
-    let transform = Transform::from_xyz(0.0, 60.0, 60.0).looking_at(Vec3::ZERO, Vec3::Y);
+fn setup_camera(mut commands: Commands, grid: Res<Grid>, map_attributes: Res<MapAttributes>) {
+    let transform = match grid.shape() {
+        MapShape::Hexagon | MapShape::Cylinder { .. } => Transform::from_xyz(0.0, 60.0, 60.0),
+        // far enough back to see the whole globe
+        MapShape::Sphere { .. } => {
+            let radius = grid.globe_radius(map_attributes.hex_size);
+            Transform::from_xyz(0.0, radius, 3.0 * radius)
+        }
+    }
+    .looking_at(Vec3::ZERO, Vec3::Y);
     commands.spawn((
         Camera3dBundle {
             transform,
@@ -335,6 +362,28 @@ fn setup_camera(mut commands: Commands) {
     });
 }
 
+/// Moves the camera back by a whole map width once it scrolls past the edge of a wrapping map
+fn wrap_camera(
+    mut cameras: Query<(&mut Transform, &mut CameraController)>,
+    grid: Res<Grid>,
+    map_attributes: Res<MapAttributes>,
+) {
+    let Some(wrap_width) = grid.wrap_width(map_attributes.hex_size) else {
+        return;
+    };
+    for (mut transform, mut controller) in cameras.iter_mut() {
+        let shift = if transform.translation.x > wrap_width / 2.0 {
+            -wrap_width
+        } else if transform.translation.x < -wrap_width / 2.0 {
+            wrap_width
+        } else {
+            continue;
+        };
+        transform.translation.x += shift;
+        controller.orbit_focus.x += shift;
+    }
+}
+
 //////////////////////////////Music//////////////////////////////
 #[derive(Component)]
 struct MyMusic;
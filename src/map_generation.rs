@@ -1,32 +1,558 @@
 
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
 
+use bevy::prelude::Vec3;
 use hexx::Hex;
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin, RidgedMulti};
+use rand::prelude::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
+use crate::grid::{Grid, MapShape};
 use crate::world::{ElevationAttributes, TemperatureAttributes};
 
-pub fn increment_height(
+////////////////////////////////////////// Terrain Generators /////////////////////////////////////////
+
+/// Heights for a fresh map along with the hexes that act as volcanoes
+pub struct Terrain {
+    pub altitude_map: HashMap<Hex, f32>,
+    pub volcano_hexes: Vec<Hex>,
+}
+
+pub trait TerrainGenerator {
+    fn generate<R: Rng + ?Sized>(
+        &self,
+        elevation_attributes: &ElevationAttributes,
+        grid: &Grid,
+        rng: &mut R,
+    ) -> Terrain;
+}
+
+/// The terrain generator picked by the `terrain_generator` config key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TerrainGeneratorConfig {
+    Volcanoes(VolcanoTerrain),
+    Noise(NoiseTerrain),
+    Plates(PlateTerrain),
+}
+
+impl Default for TerrainGeneratorConfig {
+    fn default() -> Self {
+        TerrainGeneratorConfig::Volcanoes(VolcanoTerrain)
+    }
+}
+
+impl TerrainGenerator for TerrainGeneratorConfig {
+    fn generate<R: Rng + ?Sized>(
+        &self,
+        elevation_attributes: &ElevationAttributes,
+        grid: &Grid,
+        rng: &mut R,
+    ) -> Terrain {
+        match self {
+            TerrainGeneratorConfig::Volcanoes(generator) => {
+                generator.generate(elevation_attributes, grid, rng)
+            }
+            TerrainGeneratorConfig::Noise(generator) => {
+                generator.generate(elevation_attributes, grid, rng)
+            }
+            TerrainGeneratorConfig::Plates(generator) => {
+                generator.generate(elevation_attributes, grid, rng)
+            }
+        }
+    }
+}
+
+/// Scatters volcanoes across the map and raises the land around them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VolcanoTerrain;
+
+impl TerrainGenerator for VolcanoTerrain {
+    fn generate<R: Rng + ?Sized>(
+        &self,
+        elevation_attributes: &ElevationAttributes,
+        grid: &Grid,
+        rng: &mut R,
+    ) -> Terrain {
+        let volcano_hexes: Vec<Hex> = grid
+            .tiles()
+            .choose_multiple(rng, elevation_attributes.vulcanism as usize)
+            .cloned()
+            .collect();
+        let altitude_map = generate_altitude_map(elevation_attributes, grid, &volcano_hexes, rng);
+
+        Terrain {
+            altitude_map,
+            volcano_hexes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoiseKind {
+    // smooth rolling continents
+    Fbm,
+    // sharp mountain ridges
+    Ridged,
+}
+
+/// Fractal noise heightmap, with volcanoes placed on the high ground
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NoiseTerrain {
+    pub kind: NoiseKind,
+    // features per hex, lower values give larger landmasses
+    pub frequency: f64,
+    pub octaves: usize,
+    pub lacunarity: f64,
+    pub persistence: f64,
+    // how strongly the map edges are pulled down towards the ocean floor (0 - 1)
+    pub falloff: f32,
+}
+
+impl Default for NoiseTerrain {
+    fn default() -> Self {
+        NoiseTerrain {
+            kind: NoiseKind::Fbm,
+            frequency: 0.04,
+            octaves: 6,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            falloff: 0.5,
+        }
+    }
+}
+
+impl NoiseTerrain {
+    pub const MAX_OCTAVES: usize = Fbm::<Perlin>::MAX_OCTAVES;
+
+    fn sample(&self, seed: u32, grid: &Grid) -> Vec<f64> {
+        match self.kind {
+            NoiseKind::Fbm => sample_grid(
+                &Fbm::<Perlin>::new(seed)
+                    .set_frequency(self.frequency)
+                    .set_octaves(self.octaves)
+                    .set_lacunarity(self.lacunarity)
+                    .set_persistence(self.persistence),
+                grid,
+            ),
+            NoiseKind::Ridged => sample_grid(
+                &RidgedMulti::<Perlin>::new(seed)
+                    .set_frequency(self.frequency)
+                    .set_octaves(self.octaves)
+                    .set_lacunarity(self.lacunarity)
+                    .set_persistence(self.persistence),
+                grid,
+            ),
+        }
+    }
+}
+
+/// Samples noise at every tile, across the plane of a flat map or through the inside of a globe
+/// or rolled-up cylinder, so that there is no seam where a wrapping map meets itself
+fn sample_grid<N: NoiseFn<f64, 2> + NoiseFn<f64, 3>>(noise: &N, grid: &Grid) -> Vec<f64> {
+    grid.tiles()
+        .iter()
+        .map(|hex| {
+            let position = grid.surface_position(*hex).as_dvec3();
+            match grid.shape() {
+                MapShape::Hexagon => NoiseFn::<f64, 2>::get(noise, [position.x, position.y]),
+                MapShape::Sphere { .. } | MapShape::Cylinder { .. } => {
+                    NoiseFn::<f64, 3>::get(noise, position.to_array())
+                }
+            }
+        })
+        .collect()
+}
+
+impl TerrainGenerator for NoiseTerrain {
+    fn generate<R: Rng + ?Sized>(
+        &self,
+        elevation_attributes: &ElevationAttributes,
+        grid: &Grid,
+        rng: &mut R,
+    ) -> Terrain {
+        let all_hexes = grid.tiles();
+        let samples = self.sample(rng.gen(), grid);
+
+        // pull the edges down, then stretch the result over the full elevation range
+        let heights: Vec<f32> = all_hexes
+            .iter()
+            .zip(samples)
+            .map(|(hex, sample)| {
+                let edge = grid.edge_fraction(*hex).powi(2);
+                (sample as f32 + 1.0) / 2.0 * (1.0 - self.falloff * edge)
+            })
+            .collect();
+        let lowest = heights.iter().cloned().fold(f32::INFINITY, f32::min);
+        let highest = heights.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let range = (highest - lowest).max(f32::EPSILON);
+
+        let altitude_map: HashMap<Hex, f32> = all_hexes
+            .iter()
+            .zip(heights)
+            .map(|(hex, height)| {
+                let altitude = (height - lowest) / range * elevation_attributes.highest_elevation;
+                (*hex, altitude)
+            })
+            .collect();
+
+        // volcanoes sit on the hills and mountains, falling back to any land
+        let high_ground = elevation_attributes.hill_point * elevation_attributes.highest_elevation;
+        let mut candidates: Vec<Hex> = all_hexes
+            .iter()
+            .filter(|hex| altitude_map[hex] >= high_ground)
+            .cloned()
+            .collect();
+        if candidates.len() < elevation_attributes.vulcanism as usize {
+            candidates = all_hexes
+                .iter()
+                .filter(|hex| altitude_map[hex] > elevation_attributes.sea_level)
+                .cloned()
+                .collect();
+        }
+        let volcano_hexes = candidates
+            .choose_multiple(rng, elevation_attributes.vulcanism as usize)
+            .cloned()
+            .collect();
+
+        Terrain {
+            altitude_map,
+            volcano_hexes,
+        }
+    }
+}
+
+////////////////////////////////////////// Plate Tectonics /////////////////////////////////////////
+
+/// Splits the map into drifting plates, building mountains and trenches where they meet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlateTerrain {
+    pub plates: u16,
+    // chance of each plate being ocean floor rather than continent (0 - 1)
+    pub oceanic_fraction: f32,
+    // how many rings either side of a boundary are pushed up or down
+    pub boundary_width: u16,
+    // mountain height per unit of convergence, as a fraction of the land above sea level
+    pub uplift: f32,
+    // trench depth per unit of convergence, as a fraction of the sea level
+    pub trench_depth: f32,
+    // rift depth per unit of divergence, as a fraction of the land above sea level
+    pub rift_depth: f32,
+    // height of the noise laid over the plates, in elevation units
+    pub roughness: f32,
+}
+
+impl Default for PlateTerrain {
+    fn default() -> Self {
+        PlateTerrain {
+            plates: 12,
+            oceanic_fraction: 0.6,
+            boundary_width: 3,
+            uplift: 0.8,
+            trench_depth: 0.8,
+            rift_depth: 0.3,
+            roughness: 0.5,
+        }
+    }
+}
+
+struct Plate {
+    // drift across a flat or cylindrical map, or spin about an axis through the centre of a globe
+    motion: Vec3,
+    oceanic: bool,
+}
+
+impl Plate {
+    fn velocity_at(&self, grid: &Grid, hex: Hex) -> Vec3 {
+        match grid.shape() {
+            MapShape::Hexagon | MapShape::Cylinder { .. } => self.motion,
+            MapShape::Sphere { .. } => self.motion.cross(grid.position(hex).normalize()),
+        }
+    }
+}
+
+/// Heights the plates leave behind, along with the hexes on their edges
+struct PlateBoundaries {
+    altitude_map: HashMap<Hex, f32>,
+    // on the overriding side of a subduction zone
+    subduction_zone: Vec<Hex>,
+    // pushing into a neighbouring plate
+    convergent: Vec<Hex>,
+    // next to another plate
+    boundary: Vec<Hex>,
+}
+
+impl PlateTerrain {
+    /// Grows every plate outwards from its seed hex until the whole map is claimed
+    fn partition(&self, grid: &Grid, seeds: &[Hex]) -> HashMap<Hex, usize> {
+        let mut plate_of: HashMap<Hex, usize> = HashMap::new();
+        let mut frontier: VecDeque<Hex> = VecDeque::new();
+        for (plate, seed) in seeds.iter().enumerate() {
+            plate_of.insert(*seed, plate);
+            frontier.push_back(*seed);
+        }
+
+        while let Some(hex) = frontier.pop_front() {
+            let plate = plate_of[&hex];
+            for neighbour in grid.neighbours(hex) {
+                if let Entry::Vacant(entry) = plate_of.entry(*neighbour) {
+                    entry.insert(plate);
+                    frontier.push_back(*neighbour);
+                }
+            }
+        }
+
+        // hexes cut off from every seed join the first plate
+        for hex in grid.tiles() {
+            plate_of.entry(*hex).or_insert(0);
+        }
+        plate_of
+    }
+
+    /// Sets every hex to its plate's base height, then raises mountains and sinks trenches and
+    /// rifts where the plates push into and pull away from each other
+    fn collide(
+        &self,
+        elevation_attributes: &ElevationAttributes,
+        grid: &Grid,
+        plates: &[Plate],
+        plate_of: &HashMap<Hex, usize>,
+    ) -> PlateBoundaries {
+        let sea_level = elevation_attributes.sea_level;
+        let land_range = elevation_attributes.highest_elevation - sea_level;
+        let all_hexes = grid.tiles();
+
+        // every hex starts at its plate's base height
+        let mut altitude_map: HashMap<Hex, f32> = all_hexes
+            .iter()
+            .map(|hex| {
+                let base = match plates[plate_of[hex]].oceanic {
+                    true => 0.4 * sea_level,
+                    false => sea_level + 0.1 * land_range,
+                };
+                (*hex, base)
+            })
+            .collect();
+
+        // find how hard each boundary hex is pushed into its neighbouring plate
+        let mut uplift: HashMap<Hex, f32> = HashMap::new();
+        let mut depression: HashMap<Hex, f32> = HashMap::new();
+        let mut subduction_zone: Vec<Hex> = vec![];
+        let mut convergent: Vec<Hex> = vec![];
+        let mut boundary: Vec<Hex> = vec![];
+        for hex in all_hexes {
+            let plate = plate_of[hex];
+            let mut push: f32 = 0.0;
+            let mut pull: f32 = 0.0;
+            let mut overriding = false;
+            let mut subducting = false;
+
+            for neighbour in grid.neighbours(*hex) {
+                let other = plate_of[neighbour];
+                if other == plate {
+                    continue;
+                }
+
+                // neighbouring tile centres are about one unit apart
+                let relative_velocity =
+                    plates[plate].velocity_at(grid, *hex) - plates[other].velocity_at(grid, *hex);
+                let convergence = relative_velocity.dot(grid.direction(*hex, *neighbour));
+
+                if convergence > 0.0 {
+                    push = push.max(convergence);
+                    // ocean floor dives under continents, and the higher numbered of two ocean plates
+                    let sinks = match (plates[plate].oceanic, plates[other].oceanic) {
+                        (true, false) => true,
+                        (true, true) => plate > other,
+                        _ => false,
+                    };
+                    let overrides = match (plates[plate].oceanic, plates[other].oceanic) {
+                        (false, true) => true,
+                        (true, true) => plate < other,
+                        _ => false,
+                    };
+                    subducting |= sinks;
+                    overriding |= overrides;
+                } else {
+                    pull = pull.max(-convergence);
+                }
+            }
+
+            if push == 0.0 && pull == 0.0 {
+                continue;
+            }
+            boundary.push(*hex);
+
+            if push > 0.0 {
+                convergent.push(*hex);
+                if subducting {
+                    *depression.entry(*hex).or_default() -= self.trench_depth * push * sea_level;
+                } else {
+                    *uplift.entry(*hex).or_default() += self.uplift * push * land_range;
+                }
+                if overriding {
+                    subduction_zone.push(*hex);
+                }
+            }
+            if pull > 0.0 {
+                *depression.entry(*hex).or_default() -= self.rift_depth * pull * land_range;
+            }
+        }
+
+        // spread the boundary effects into the plates, fading with distance
+        let spread = |changes: &HashMap<Hex, f32>, altitude_map: &mut HashMap<Hex, f32>| {
+            let mut strongest: HashMap<Hex, f32> = HashMap::new();
+            for hex in boundary.iter() {
+                let Some(change) = changes.get(hex) else {
+                    continue;
+                };
+                for (distance, ring) in grid.rings(*hex, self.boundary_width).iter().enumerate() {
+                    let fade = 1.0 - distance as f32 / (self.boundary_width + 1) as f32;
+                    for ring_hex in ring {
+                        // effects stay on the plate they started on
+                        if plate_of[ring_hex] != plate_of[hex] {
+                            continue;
+                        }
+                        let entry = strongest.entry(*ring_hex).or_default();
+                        if (change * fade).abs() > entry.abs() {
+                            *entry = change * fade;
+                        }
+                    }
+                }
+            }
+            for (hex, change) in strongest {
+                *altitude_map.get_mut(&hex).unwrap() += change;
+            }
+        };
+        spread(&uplift, &mut altitude_map);
+        spread(&depression, &mut altitude_map);
+
+        PlateBoundaries {
+            altitude_map,
+            subduction_zone,
+            convergent,
+            boundary,
+        }
+    }
+}
+
+impl TerrainGenerator for PlateTerrain {
+    fn generate<R: Rng + ?Sized>(
+        &self,
+        elevation_attributes: &ElevationAttributes,
+        grid: &Grid,
+        rng: &mut R,
+    ) -> Terrain {
+        let all_hexes = grid.tiles();
+
+        let seeds: Vec<Hex> = all_hexes
+            .choose_multiple(rng, self.plates as usize)
+            .cloned()
+            .collect();
+        let plates: Vec<Plate> = seeds
+            .iter()
+            .map(|_| {
+                let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+                let direction = match grid.shape() {
+                    MapShape::Hexagon | MapShape::Cylinder { .. } => {
+                        Vec3::new(angle.cos(), angle.sin(), 0.0)
+                    }
+                    MapShape::Sphere { .. } => {
+                        // uniformly spread axis of rotation
+                        let z: f32 = rng.gen_range(-1.0..1.0);
+                        let xy = (1.0 - z * z).sqrt();
+                        Vec3::new(xy * angle.cos(), xy * angle.sin(), z)
+                    }
+                };
+                let speed = rng.gen_range(0.2..1.0);
+                Plate {
+                    motion: direction * speed,
+                    oceanic: rng.gen::<f32>() < self.oceanic_fraction,
+                }
+            })
+            .collect();
+        let plate_of = self.partition(grid, &seeds);
+        let PlateBoundaries {
+            mut altitude_map,
+            subduction_zone,
+            convergent,
+            boundary,
+        } = self.collide(elevation_attributes, grid, &plates, &plate_of);
+
+        let roughness = sample_grid(&Fbm::<Perlin>::new(rng.gen()).set_frequency(0.1), grid);
+        for (hex, noise) in all_hexes.iter().zip(roughness) {
+            let altitude = altitude_map.get_mut(hex).unwrap();
+            *altitude += noise as f32 * self.roughness;
+            *altitude = altitude.clamp(0.0, elevation_attributes.highest_elevation);
+        }
+
+        // volcanoes line the overriding side of subduction zones, then any other colliding edge
+        let vulcanism = elevation_attributes.vulcanism as usize;
+        let mut volcano_hexes: Vec<Hex> = subduction_zone
+            .choose_multiple(rng, vulcanism)
+            .cloned()
+            .collect();
+        for fallback in [&convergent, &boundary] {
+            if volcano_hexes.len() >= vulcanism {
+                break;
+            }
+            let remaining: Vec<Hex> = fallback
+                .iter()
+                .filter(|hex| !volcano_hexes.contains(hex))
+                .cloned()
+                .collect();
+            volcano_hexes.extend(
+                remaining
+                    .choose_multiple(rng, vulcanism - volcano_hexes.len())
+                    .cloned()
+                    .collect::<Vec<Hex>>(),
+            );
+        }
+
+        Terrain {
+            altitude_map,
+            volcano_hexes,
+        }
+    }
+}
+
+////////////////////////////////////////// Volcano Raising /////////////////////////////////////////
+
+pub fn increment_height<R: Rng + ?Sized>(
     elevation_attributes: &ElevationAttributes,
     current_height: &mut f32,
     distance_from_volcano: u16,
+    rng: &mut R,
 ) {
     let probability = 1.0 - (distance_from_volcano as f32 / elevation_attributes.mountain_spread);
-    if rand::random::<f32>() < probability {
+    if rng.gen::<f32>() < probability {
         *current_height += elevation_attributes.elevation_increment;
     }
 }
 
-pub fn generate_altitude_map(
+pub fn generate_altitude_map<R: Rng + ?Sized>(
     elevation_attributes: &ElevationAttributes,
-    all_hexes: &Vec<Hex>,
+    grid: &Grid,
     volcano_hexes: &Vec<Hex>,
+    rng: &mut R,
 ) -> HashMap<Hex, f32> {
-    let mut altitude_map: HashMap<Hex, f32> = all_hexes.iter().map(|hex| (*hex, 0.0)).collect();
+    let mut altitude_map: HashMap<Hex, f32> = grid.tiles().iter().map(|hex| (*hex, 0.0)).collect();
     for hex in volcano_hexes {
         altitude_map.insert(*hex, elevation_attributes.elevation_increment);
     }
 
-    raise_volcanoes(elevation_attributes, &mut altitude_map, volcano_hexes);
+    raise_volcanoes(
+        elevation_attributes,
+        grid,
+        &mut altitude_map,
+        volcano_hexes,
+        rng,
+    );
     altitude_map
 }
 
@@ -39,21 +565,32 @@ pub fn generate_altitude_map(
  *
  * the probability of a tile being raised is 1 - (distance from volcano / mountain spread)")
  */
-fn raise_volcanoes(
+fn raise_volcanoes<R: Rng + ?Sized>(
     elevation_attributes: &ElevationAttributes,
+    grid: &Grid,
     altitude_map: &mut HashMap<Hex, f32>,
     volcano_hexes: &Vec<Hex>,
+    rng: &mut R,
 ) {
+    let volcano_rings: Vec<Vec<Vec<Hex>>> = volcano_hexes
+        .iter()
+        .map(|hex| grid.rings(*hex, elevation_attributes.mountain_spread as u16))
+        .collect();
+
     let mut max_height = 0.0;
     while max_height < elevation_attributes.highest_elevation {
-        for hex in volcano_hexes {
-            increment_height(elevation_attributes, altitude_map.get_mut(hex).unwrap(), 0);
+        for (hex, rings) in volcano_hexes.iter().zip(volcano_rings.iter()) {
+            increment_height(
+                elevation_attributes,
+                altitude_map.get_mut(hex).unwrap(),
+                0,
+                rng,
+            );
             max_height = max_height.max(altitude_map[hex]);
-            for rings_traversed in 1..=elevation_attributes.mountain_spread as u16 {
-                for neighbour in hex.ring(rings_traversed as u32) {
-                    if let Some(height) = altitude_map.get_mut(&neighbour) {
-                        increment_height(elevation_attributes, height, rings_traversed);
-                    }
+            for (rings_traversed, ring) in rings.iter().enumerate().skip(1) {
+                for neighbour in ring {
+                    let height = altitude_map.get_mut(neighbour).unwrap();
+                    increment_height(elevation_attributes, height, rings_traversed as u16, rng);
                 }
             }
         }
@@ -62,20 +599,22 @@ fn raise_volcanoes(
 
 pub fn get_distances_from_volcanos(
     elevation_attributes: &ElevationAttributes,
+    grid: &Grid,
     volcano_hexes: &Vec<Hex>,
 ) -> HashMap<Hex, Vec<u16>> {
     let mut distances_from_volcanos: HashMap<Hex, Vec<u16>> = HashMap::new();
 
     for hex in volcano_hexes {
         distances_from_volcanos.insert(*hex, vec![0]);
-        for rings_traversed in 1..=elevation_attributes.mountain_spread as u16 {
-            for neighbour in hex.ring(rings_traversed as u32) {
+        let rings = grid.rings(*hex, elevation_attributes.mountain_spread as u16);
+        for (rings_traversed, ring) in rings.iter().enumerate().skip(1) {
+            for neighbour in ring {
                 let mut distances = distances_from_volcanos
-                    .get(&neighbour)
+                    .get(neighbour)
                     .unwrap_or(&vec![])
                     .clone();
-                distances.push(rings_traversed);
-                distances_from_volcanos.insert(neighbour, distances.clone());
+                distances.push(rings_traversed as u16);
+                distances_from_volcanos.insert(*neighbour, distances.clone());
             }
         }
     }
@@ -83,17 +622,59 @@ pub fn get_distances_from_volcanos(
     distances_from_volcanos
 }
 
+// distance inland, in tiles, over which the moisture of a fresh map halves
+const MOISTURE_HALF_DISTANCE: f32 = 4.0;
+
+/// Rough humidity of a fresh map (0 - 1), falling off with the distance from the sea
+pub fn generate_moisture_map(
+    elevation_attributes: &ElevationAttributes,
+    grid: &Grid,
+    altitude_map: &HashMap<Hex, f32>,
+) -> HashMap<Hex, f32> {
+    let mut distance_to_sea: HashMap<Hex, u32> = HashMap::new();
+    let mut frontier: VecDeque<Hex> = VecDeque::new();
+    for hex in grid.tiles() {
+        if altitude_map[hex] <= elevation_attributes.sea_level {
+            distance_to_sea.insert(*hex, 0);
+            frontier.push_back(*hex);
+        }
+    }
+
+    while let Some(hex) = frontier.pop_front() {
+        let distance = distance_to_sea[&hex] + 1;
+        for neighbour in grid.neighbours(hex) {
+            if let Entry::Vacant(entry) = distance_to_sea.entry(*neighbour) {
+                entry.insert(distance);
+                frontier.push_back(*neighbour);
+            }
+        }
+    }
+
+    // a map without any sea is dry everywhere
+    grid.tiles()
+        .iter()
+        .map(|hex| {
+            let moisture = match distance_to_sea.get(hex) {
+                Some(distance) => 0.5_f32.powf(*distance as f32 / MOISTURE_HALF_DISTANCE),
+                None => 0.0,
+            };
+            (*hex, moisture)
+        })
+        .collect()
+}
+
 pub fn generate_temperature_map(
     temperature_attributes: &TemperatureAttributes,
-    map_radius: u16,
+    grid: &Grid,
     altitude_map: &HashMap<Hex, f32>,
 ) -> HashMap<Hex, f32> {
     altitude_map
         .iter()
         .map(|(hex, &altitude)| {
+            let latitude = grid.geo_coordinates(*hex).latitude;
             (
                 *hex,
-                calculate_temperature(temperature_attributes, map_radius, altitude, hex.y as f32),
+                calculate_temperature(temperature_attributes, altitude, latitude),
             )
         })
         .collect()
@@ -101,11 +682,10 @@ pub fn generate_temperature_map(
 
 fn calculate_temperature(
     temperature_attributes: &TemperatureAttributes,
-    map_radius: u16,
     altitude: f32,
     latitude: f32,
 ) -> f32 {
-    let normalized_y = latitude.abs() / map_radius as f32;
+    let normalized_y = latitude.abs() / 90.0;
     let latitude_temperature_mod =
         normalized_y * temperature_attributes.latitude_temperature_variation;
     let altitude_temperature_mod = if altitude > 0.0 {
@@ -115,3 +695,95 @@ fn calculate_temperature(
     };
     temperature_attributes.base_temperature - altitude_temperature_mod - latitude_temperature_mod
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::world::MapAttributes;
+
+    fn grid(map_shape: MapShape) -> Grid {
+        Grid::new(&MapAttributes {
+            hex_size: 1.0,
+            map_radius: 8,
+            map_shape,
+        })
+    }
+
+    /// Two continental plates meeting along a line through the middle of a hexagon
+    fn two_plates(grid: &Grid, drift: f32) -> (Vec<Plate>, HashMap<Hex, usize>) {
+        let plates = vec![
+            Plate {
+                motion: Vec3::X * drift,
+                oceanic: false,
+            },
+            Plate {
+                motion: Vec3::NEG_X * drift,
+                oceanic: false,
+            },
+        ];
+        let plate_of = grid
+            .tiles()
+            .iter()
+            .map(|hex| (*hex, if hex.x < 0 { 0 } else { 1 }))
+            .collect();
+        (plates, plate_of)
+    }
+
+    #[test]
+    fn every_tile_belongs_to_one_plate() {
+        for shape in [MapShape::Hexagon, MapShape::Sphere { subdivisions: 4 }] {
+            let grid = grid(shape);
+            let tiles = grid.tiles();
+            let seeds = [tiles[0], tiles[tiles.len() / 2], tiles[tiles.len() - 1]];
+            let plate_of = PlateTerrain::default().partition(&grid, &seeds);
+
+            assert_eq!(plate_of.len(), tiles.len());
+            assert!(tiles
+                .iter()
+                .all(|hex| plate_of.get(hex).is_some_and(|plate| *plate < seeds.len())));
+            for (plate, seed) in seeds.iter().enumerate() {
+                assert_eq!(plate_of[seed], plate);
+            }
+        }
+    }
+
+    #[test]
+    fn converging_plates_raise_their_boundary() {
+        let elevation_attributes = ElevationAttributes::from(&Config::defaults());
+        let base = elevation_attributes.sea_level
+            + 0.1 * (elevation_attributes.highest_elevation - elevation_attributes.sea_level);
+        let grid = grid(MapShape::Hexagon);
+        let (plates, plate_of) = two_plates(&grid, 1.0);
+
+        let boundaries =
+            PlateTerrain::default().collide(&elevation_attributes, &grid, &plates, &plate_of);
+
+        assert!(!boundaries.convergent.is_empty());
+        for hex in &boundaries.convergent {
+            assert!(boundaries.altitude_map[hex] > base);
+        }
+        assert!(boundaries
+            .altitude_map
+            .values()
+            .all(|altitude| *altitude >= base));
+    }
+
+    #[test]
+    fn diverging_plates_lower_their_boundary() {
+        let elevation_attributes = ElevationAttributes::from(&Config::defaults());
+        let base = elevation_attributes.sea_level
+            + 0.1 * (elevation_attributes.highest_elevation - elevation_attributes.sea_level);
+        let grid = grid(MapShape::Hexagon);
+        let (plates, plate_of) = two_plates(&grid, -1.0);
+
+        let boundaries =
+            PlateTerrain::default().collide(&elevation_attributes, &grid, &plates, &plate_of);
+
+        assert!(boundaries.convergent.is_empty());
+        assert!(!boundaries.boundary.is_empty());
+        for hex in &boundaries.boundary {
+            assert!(boundaries.altitude_map[hex] < base);
+        }
+    }
+}
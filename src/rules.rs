@@ -0,0 +1,532 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::config::ConfigIssue;
+use crate::terrain::TileType;
+use crate::world::{EcosystemAttributes, ElevationAttributes};
+
+/// Built-in terrain transition rules, used unless a rules file is given
+const BUILTIN_RULES: &str = include_str!("../rules.json");
+
+// environment variable used to pick a rules file when no flag is given
+pub const RULES_ENV_VAR: &str = "TERRAFLOW_RULES";
+
+// weight given to a tile staying as it is when none of a driver's rules match
+const CERTAIN: f32 = 1.0;
+
+/// World attributes that thresholds can refer to by name, next to the rules file's own `params`
+const WORLD_PARAMS: [&str; 4] = [
+    "mountain_point",
+    "hill_point",
+    "sea_level",
+    "terrain_change_sensitivity",
+];
+
+////////////////////////////////////////// Rules /////////////////////////////////////////
+
+/// How tiles morph into other tile types as the weather changes them.
+///
+/// Every driver picks the first of its rules that lists the tile and whose condition holds,
+/// and adds that rule's weighted targets to the tile's options. A driver without a matching
+/// rule votes for the tile staying as it is. The new tile type is drawn from all the options.
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
+#[serde(deny_unknown_fields)]
+pub struct TransitionRules {
+    #[serde(default)]
+    pub params: BTreeMap<String, f32>,
+    pub drivers: BTreeMap<Driver, Vec<TransitionRule>>,
+}
+
+/// Groups of rules that each get a say in what a tile becomes, in this order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Driver {
+    Humidity,
+    Groundwater,
+    Elevation,
+    Temperature,
+    Biomass,
+    Ice,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransitionRule {
+    pub tiles: Vec<TileType>,
+    // always applies when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<Condition>,
+    // tile types and their relative weights
+    pub becomes: Vec<(TileType, f32)>,
+}
+
+/// Holds when the measure is strictly above `above` and strictly below `below`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    pub measure: Measure,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub above: Option<Threshold>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub below: Option<Threshold>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Measure {
+    Humidity,
+    // groundwater height
+    Water,
+    // groundwater height compared to the soil height
+    WaterAboveSoil,
+    // bedrock height as a fraction of highest_elevation
+    Elevation,
+    // degrees
+    Temperature,
+    // share of the ground covered by vegetation
+    Biomass,
+    // water that rose out of the aquifer during the last epoch
+    Spring,
+    // glacier thickness
+    Ice,
+}
+
+/// A plain number, or a named param multiplied by `scale`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Threshold {
+    Value(f32),
+    Param {
+        param: String,
+        #[serde(default = "unit_scale")]
+        scale: f32,
+    },
+}
+
+fn unit_scale() -> f32 {
+    1.0
+}
+
+/// The state of a tile that rules are checked against
+pub struct TileConditions {
+    pub humidity: f32,
+    pub water: f32,
+    pub soil: f32,
+    pub elevation: f32,
+    pub temperature: f32,
+    pub biomass: f32,
+    pub spring: f32,
+    pub ice: f32,
+}
+
+impl Measure {
+    /// The measured value and the value thresholds are added to
+    fn values(&self, conditions: &TileConditions) -> (f32, f32) {
+        match self {
+            Measure::Humidity => (conditions.humidity, 0.0),
+            Measure::Water => (conditions.water, 0.0),
+            Measure::WaterAboveSoil => (conditions.water, conditions.soil),
+            Measure::Elevation => (conditions.elevation, 0.0),
+            Measure::Temperature => (conditions.temperature, 0.0),
+            Measure::Biomass => (conditions.biomass, 0.0),
+            Measure::Spring => (conditions.spring, 0.0),
+            Measure::Ice => (conditions.ice, 0.0),
+        }
+    }
+}
+
+impl Threshold {
+    fn resolve(&self, params: &HashMap<&str, f32>) -> f32 {
+        match self {
+            Threshold::Value(value) => *value,
+            Threshold::Param { param, scale } => params[param.as_str()] * scale,
+        }
+    }
+}
+
+impl Condition {
+    pub fn matches(&self, conditions: &TileConditions, params: &HashMap<&str, f32>) -> bool {
+        let (value, reference) = self.measure.values(conditions);
+        self.above
+            .as_ref()
+            .is_none_or(|above| value > reference + above.resolve(params))
+            && self
+                .below
+                .as_ref()
+                .is_none_or(|below| value < reference + below.resolve(params))
+    }
+}
+
+impl Default for TransitionRules {
+    fn default() -> Self {
+        serde_json::from_str(BUILTIN_RULES).expect("built-in rules.json is invalid")
+    }
+}
+
+impl TransitionRules {
+    pub fn from_path(path: &Path) -> Result<Self, RulesError> {
+        let rules_str = std::fs::read_to_string(path).map_err(|error| RulesError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Self::from_json(&rules_str)
+    }
+
+    pub fn from_json(rules_str: &str) -> Result<Self, RulesError> {
+        let rules: TransitionRules = serde_json::from_str(rules_str).map_err(RulesError::Parse)?;
+        let issues = rules.validate();
+        if !issues.is_empty() {
+            return Err(RulesError::Invalid(issues));
+        }
+        Ok(rules)
+    }
+
+    /// Every value a threshold can refer to by name
+    pub fn params(
+        &self,
+        elevation_attributes: &ElevationAttributes,
+        ecosystem_attributes: &EcosystemAttributes,
+    ) -> HashMap<&str, f32> {
+        let world_values = [
+            elevation_attributes.mountain_point,
+            elevation_attributes.hill_point,
+            elevation_attributes.sea_level,
+            ecosystem_attributes.terrain_change_sensitivity,
+        ];
+        let mut params: HashMap<&str, f32> = WORLD_PARAMS.into_iter().zip(world_values).collect();
+        params.extend(
+            self.params
+                .iter()
+                .map(|(name, value)| (name.as_str(), *value)),
+        );
+        params
+    }
+
+    /// Weighted tile types a tile may turn into this epoch, including staying as it is
+    pub fn transitions(
+        &self,
+        tile_type: TileType,
+        conditions: &TileConditions,
+        params: &HashMap<&str, f32>,
+    ) -> Vec<(TileType, f32)> {
+        let mut probabilities = vec![];
+        for rules in self.drivers.values() {
+            let rule = rules.iter().find(|rule| {
+                rule.tiles.contains(&tile_type)
+                    && rule
+                        .when
+                        .as_ref()
+                        .is_none_or(|condition| condition.matches(conditions, params))
+            });
+            match rule {
+                Some(rule) => probabilities.extend(rule.becomes.iter().cloned()),
+                None => probabilities.push((tile_type, CERTAIN)),
+            }
+        }
+
+        if probabilities.is_empty() {
+            return vec![(tile_type, CERTAIN)];
+        }
+        probabilities
+    }
+
+    /// Checks for rules that refer to unknown params, can never be reached or pick nothing
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = vec![];
+
+        for name in self.params.keys() {
+            if WORLD_PARAMS.contains(&name.as_str()) {
+                issues.push(ConfigIssue::Invalid {
+                    key: format!("params.{}", name),
+                    reason: "has the same name as a world attribute",
+                });
+            }
+        }
+
+        for (driver, rules) in &self.drivers {
+            for (index, rule) in rules.iter().enumerate() {
+                let prefix = format!("drivers.{}[{}]", driver.name(), index);
+
+                if rule.tiles.is_empty() {
+                    issues.push(ConfigIssue::Invalid {
+                        key: format!("{}.tiles", prefix),
+                        reason: "applies to no tiles",
+                    });
+                }
+
+                if let Some(condition) = &rule.when {
+                    if condition.above.is_none() && condition.below.is_none() {
+                        issues.push(ConfigIssue::Invalid {
+                            key: format!("{}.when", prefix),
+                            reason: "needs an `above` or a `below` threshold",
+                        });
+                    }
+                    for (side, threshold) in
+                        [("above", &condition.above), ("below", &condition.below)]
+                    {
+                        if let Some(Threshold::Param { param, .. }) = threshold {
+                            if !WORLD_PARAMS.contains(&param.as_str())
+                                && !self.params.contains_key(param)
+                            {
+                                issues.push(ConfigIssue::UnknownName {
+                                    key: format!("{}.when.{}.param", prefix, side),
+                                    name: param.clone(),
+                                });
+                            }
+                        }
+                    }
+                }
+
+                for (tile_type, weight) in &rule.becomes {
+                    if *weight < 0.0 {
+                        issues.push(ConfigIssue::OutOfRange {
+                            key: format!("{}.becomes.{:?}", prefix, tile_type),
+                            value: *weight,
+                            reason: "must not be negative",
+                        });
+                    }
+                }
+                let total_weight: f32 = rule.becomes.iter().map(|(_, weight)| weight).sum();
+                if total_weight <= 0.0 {
+                    issues.push(ConfigIssue::OutOfRange {
+                        key: format!("{}.becomes", prefix),
+                        value: total_weight,
+                        reason: "weights must add up to more than 0",
+                    });
+                }
+
+                // an earlier rule for the same tile with the same (or no) condition always wins
+                for tile_type in &rule.tiles {
+                    let shadowed = rules[..index].iter().any(|earlier| {
+                        earlier.tiles.contains(tile_type)
+                            && (earlier.when.is_none() || earlier.when == rule.when)
+                    });
+                    if shadowed {
+                        issues.push(ConfigIssue::Invalid {
+                            key: format!("{}.tiles.{:?}", prefix, tile_type),
+                            reason: "is never reached because an earlier rule always matches first",
+                        });
+                    }
+                }
+            }
+        }
+
+        issues
+    }
+}
+
+impl Driver {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Driver::Humidity => "humidity",
+            Driver::Groundwater => "groundwater",
+            Driver::Elevation => "elevation",
+            Driver::Temperature => "temperature",
+            Driver::Biomass => "biomass",
+            Driver::Ice => "ice",
+        }
+    }
+}
+
+////////////////////////////////////////// Errors /////////////////////////////////////////
+
+#[derive(Debug)]
+pub enum RulesError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Parse(serde_json::Error),
+    Invalid(Vec<ConfigIssue>),
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RulesError::Io { path, error } => {
+                write!(f, "could not read rules {}: {}", path.display(), error)
+            }
+            RulesError::Parse(error) => write!(f, "could not parse rules: {}", error),
+            RulesError::Invalid(issues) => {
+                write!(f, "invalid rules:")?;
+                for issue in issues {
+                    write!(f, "\n  - {}", issue)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RulesError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issues(rules: &str) -> Vec<ConfigIssue> {
+        match TransitionRules::from_json(rules) {
+            Err(RulesError::Invalid(issues)) => issues,
+            other => panic!("expected {} to be invalid, got {:?}", rules, other),
+        }
+    }
+
+    #[test]
+    fn builtin_rules_are_valid() {
+        let rules: TransitionRules = serde_json::from_str(BUILTIN_RULES).unwrap();
+        assert_eq!(rules.validate(), vec![]);
+    }
+
+    #[test]
+    fn rule_after_an_unconditional_one_is_shadowed() {
+        assert_eq!(
+            issues(
+                r#"{ "drivers": { "humidity": [
+                    { "tiles": ["Grass"], "becomes": [["Dirt", 1.0]] },
+                    { "tiles": ["Grass", "Forest"], "becomes": [["Water", 1.0]] }
+                ] } }"#
+            ),
+            vec![ConfigIssue::Invalid {
+                key: "drivers.humidity[1].tiles.Grass".to_string(),
+                reason: "is never reached because an earlier rule always matches first",
+            }]
+        );
+    }
+
+    #[test]
+    fn rule_after_one_with_the_same_condition_is_shadowed() {
+        assert_eq!(
+            issues(
+                r#"{ "drivers": { "temperature": [
+                    {
+                        "tiles": ["Water"],
+                        "when": { "measure": "temperature", "below": 0.0 },
+                        "becomes": [["Ice", 1.0]]
+                    },
+                    {
+                        "tiles": ["Water"],
+                        "when": { "measure": "temperature", "below": 0.0 },
+                        "becomes": [["Swamp", 1.0]]
+                    }
+                ] } }"#
+            ),
+            vec![ConfigIssue::Invalid {
+                key: "drivers.temperature[1].tiles.Water".to_string(),
+                reason: "is never reached because an earlier rule always matches first",
+            }]
+        );
+    }
+
+    #[test]
+    fn rules_with_different_conditions_are_not_shadowed() {
+        let rules = r#"{ "drivers": { "temperature": [
+            {
+                "tiles": ["Water"],
+                "when": { "measure": "temperature", "below": 0.0 },
+                "becomes": [["Ice", 1.0]]
+            },
+            {
+                "tiles": ["Water"],
+                "when": { "measure": "temperature", "above": 40.0 },
+                "becomes": [["Desert", 1.0]]
+            }
+        ] } }"#;
+        assert!(TransitionRules::from_json(rules).is_ok());
+    }
+
+    #[test]
+    fn unknown_params_are_reported() {
+        assert_eq!(
+            issues(
+                r#"{ "params": { "wet": 0.5 }, "drivers": { "humidity": [
+                    {
+                        "tiles": ["Dirt"],
+                        "when": { "measure": "humidity", "above": { "param": "wet" } },
+                        "becomes": [["Grass", 1.0]]
+                    },
+                    {
+                        "tiles": ["Grass"],
+                        "when": {
+                            "measure": "elevation",
+                            "above": { "param": "hill_point" },
+                            "below": { "param": "dry", "scale": 0.5 }
+                        },
+                        "becomes": [["Dirt", 1.0]]
+                    }
+                ] } }"#
+            ),
+            vec![ConfigIssue::UnknownName {
+                key: "drivers.humidity[1].when.below.param".to_string(),
+                name: "dry".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn params_named_like_world_attributes_are_reported() {
+        assert_eq!(
+            issues(r#"{ "params": { "sea_level": 1.0 }, "drivers": {} }"#),
+            vec![ConfigIssue::Invalid {
+                key: "params.sea_level".to_string(),
+                reason: "has the same name as a world attribute",
+            }]
+        );
+    }
+
+    #[test]
+    fn weights_adding_up_to_zero_are_reported() {
+        assert_eq!(
+            issues(
+                r#"{ "drivers": { "humidity": [
+                    { "tiles": ["Grass"], "becomes": [["Dirt", 0.0]] }
+                ] } }"#
+            ),
+            vec![ConfigIssue::OutOfRange {
+                key: "drivers.humidity[0].becomes".to_string(),
+                value: 0.0,
+                reason: "weights must add up to more than 0",
+            }]
+        );
+    }
+
+    #[test]
+    fn negative_weights_are_reported() {
+        assert_eq!(
+            issues(
+                r#"{ "drivers": { "humidity": [
+                    { "tiles": ["Grass"], "becomes": [["Dirt", 2.0], ["Desert", -1.0]] }
+                ] } }"#
+            ),
+            vec![ConfigIssue::OutOfRange {
+                key: "drivers.humidity[0].becomes.Desert".to_string(),
+                value: -1.0,
+                reason: "must not be negative",
+            }]
+        );
+    }
+
+    #[test]
+    fn rules_without_tiles_or_thresholds_are_reported() {
+        assert_eq!(
+            issues(
+                r#"{ "drivers": { "humidity": [
+                    { "tiles": [], "becomes": [["Dirt", 1.0]] },
+                    { "tiles": ["Grass"], "when": { "measure": "humidity" }, "becomes": [["Dirt", 1.0]] }
+                ] } }"#
+            ),
+            vec![
+                ConfigIssue::Invalid {
+                    key: "drivers.humidity[0].tiles".to_string(),
+                    reason: "applies to no tiles",
+                },
+                ConfigIssue::Invalid {
+                    key: "drivers.humidity[1].when".to_string(),
+                    reason: "needs an `above` or a `below` threshold",
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,280 @@
+use bevy::prelude::*;
+use hexx::Hex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use crate::atmosphere::Atmosphere;
+use crate::components::{
+    Aquifer, Biomass, DebugWeatherBundle, DistancesFromVolcano, ElevationBundle, HexCoordinates,
+    HigherNeighbours, Humidity, IncomingOverflow, Lake, LowerNeighbours,
+    PendingHumidityRedistribution, Snowpack, Temperature, TileTypeChanged, Wind,
+};
+use crate::grid::Grid;
+use crate::simulation::neighbours_of;
+use crate::terrain::TileType;
+use crate::utils::WorldRng;
+use crate::water_budget::WaterBudget;
+use crate::world::WorldAttributes;
+use crate::{Epochs, HexToEntity};
+
+/// Version of the save format, checked when a save is read.
+///
+/// Parts of a save that are `#[serde(default)]` can be left out, and start from their defaults.
+pub const SAVE_FORMAT_VERSION: u32 = 1;
+
+/// Everything needed to continue a world exactly where it was saved
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
+pub struct SaveFile {
+    pub version: u32,
+    pub epochs: u32,
+    pub rng: WorldRng,
+    pub attributes: WorldAttributes,
+    pub tiles: Vec<TileSave>,
+    // water the ocean reservoir has gained
+    #[serde(default)]
+    pub ocean_balance: f32,
+    // worlds loaded without one start out with their configured air
+    #[serde(default)]
+    pub atmosphere: Option<Atmosphere>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TileSave {
+    pub hex: (i32, i32),
+    pub tile_type: TileType,
+    pub elevation: ElevationBundle,
+    pub humidity: Humidity,
+    pub temperature: Temperature,
+    pub distances_from_volcano: Option<DistancesFromVolcano>,
+    pub pending_humidity: PendingHumidityRedistribution,
+    pub incoming_overflow: IncomingOverflow,
+    pub weather: DebugWeatherBundle,
+    // neighbour heights from the last epoch, stored by hex
+    pub higher_neighbours: Option<Vec<((i32, i32), f32)>>,
+    pub lower_neighbours: Option<Vec<((i32, i32), f32)>>,
+    pub tile_type_changed: bool,
+    // lake level and spill hex
+    #[serde(default)]
+    pub lake: Option<(f32, (i32, i32))>,
+    // tiles without one start from the biomass of their type
+    #[serde(default)]
+    pub biomass: Option<Biomass>,
+    // tiles without one start with an empty aquifer
+    #[serde(default)]
+    pub aquifer: Option<Aquifer>,
+    #[serde(default)]
+    pub snowpack: Option<Snowpack>,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Io(error) => write!(f, "could not access save file: {}", error),
+            SaveError::Format(error) => write!(f, "invalid save file: {}", error),
+            SaveError::UnsupportedVersion(version) => write!(
+                f,
+                "save file version {} is not supported (expected {})",
+                version, SAVE_FORMAT_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<std::io::Error> for SaveError {
+    fn from(error: std::io::Error) -> Self {
+        SaveError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(error: serde_json::Error) -> Self {
+        SaveError::Format(error)
+    }
+}
+
+impl SaveFile {
+    /// Captures the current state of every tile along with the world resources.
+    ///
+    /// Tiles are stored in query order so that a loaded world iterates them the same way.
+    pub fn capture(world: &mut World) -> Self {
+        let entity_to_hex: HashMap<Entity, (i32, i32)> = world
+            .query::<(Entity, &HexCoordinates)>()
+            .iter(world)
+            .map(|(entity, hex)| (entity, (hex.0.x, hex.0.y)))
+            .collect();
+        let by_hex = |ids: &Vec<(Entity, f32)>| {
+            ids.iter()
+                .map(|(id, height)| (entity_to_hex[id], *height))
+                .collect::<Vec<((i32, i32), f32)>>()
+        };
+
+        let mut query = world.query::<(
+            &HexCoordinates,
+            &TileType,
+            &ElevationBundle,
+            &Humidity,
+            &Temperature,
+            // queries take at most 15 components, so the layers added later are grouped
+            (&Biomass, &Aquifer, &Snowpack),
+            &PendingHumidityRedistribution,
+            &IncomingOverflow,
+            &DebugWeatherBundle,
+            Option<&DistancesFromVolcano>,
+            Option<&HigherNeighbours>,
+            Option<&LowerNeighbours>,
+            Option<&TileTypeChanged>,
+            Entity,
+        )>();
+        // lakes live outside the tile tables, so they are looked up separately to keep the order
+        let lakes: HashMap<Entity, Lake> = world
+            .query::<(Entity, &Lake)>()
+            .iter(world)
+            .map(|(entity, lake)| (entity, *lake))
+            .collect();
+
+        let tiles = query
+            .iter(world)
+            .map(
+                |(
+                    hex,
+                    tile_type,
+                    elevation,
+                    humidity,
+                    temperature,
+                    (biomass, aquifer, snowpack),
+                    pending_humidity,
+                    incoming_overflow,
+                    weather,
+                    distances_from_volcano,
+                    higher_neighbours,
+                    lower_neighbours,
+                    tile_type_changed,
+                    entity,
+                )| TileSave {
+                    hex: (hex.0.x, hex.0.y),
+                    tile_type: *tile_type,
+                    elevation: *elevation,
+                    humidity: *humidity,
+                    temperature: *temperature,
+                    distances_from_volcano: distances_from_volcano.cloned(),
+                    pending_humidity: pending_humidity.clone(),
+                    incoming_overflow: incoming_overflow.clone(),
+                    weather: weather.clone(),
+                    higher_neighbours: higher_neighbours.map(|higher| by_hex(&higher.ids)),
+                    lower_neighbours: lower_neighbours.map(|lower| by_hex(&lower.ids)),
+                    tile_type_changed: tile_type_changed.is_some(),
+                    lake: lakes
+                        .get(&entity)
+                        .map(|lake| (lake.level, (lake.spill.x, lake.spill.y))),
+                    biomass: Some(*biomass),
+                    aquifer: Some(*aquifer),
+                    snowpack: Some(*snowpack),
+                },
+            )
+            .collect();
+
+        SaveFile {
+            version: SAVE_FORMAT_VERSION,
+            epochs: world.resource::<Epochs>().epochs,
+            rng: world.resource::<WorldRng>().clone(),
+            attributes: world.resource::<WorldAttributes>().clone(),
+            tiles,
+            ocean_balance: world.resource::<WaterBudget>().ocean_balance,
+            atmosphere: Some(world.resource::<Atmosphere>().clone()),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), SaveError> {
+        serde_json::to_writer(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self, SaveError> {
+        let save: SaveFile = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if save.version != SAVE_FORMAT_VERSION {
+            return Err(SaveError::UnsupportedVersion(save.version));
+        }
+        Ok(save)
+    }
+}
+
+/// Respawns the saved tiles, rebuilding `Neighbours`, `GeoCoordinates` and `HexToEntity` from the grid
+pub fn load_grid(mut commands: Commands, save: Res<SaveFile>, grid: Res<Grid>) {
+    let hex_to_entity: HashMap<Hex, Entity> = save
+        .tiles
+        .iter()
+        .map(|tile| {
+            (
+                Hex::new(tile.hex.0, tile.hex.1),
+                commands.spawn_empty().id(),
+            )
+        })
+        .collect();
+    let by_entity = |ids: &Vec<((i32, i32), f32)>| {
+        ids.iter()
+            .map(|((x, y), height)| (hex_to_entity[&Hex::new(*x, *y)], *height))
+            .collect::<Vec<(Entity, f32)>>()
+    };
+
+    // components are inserted in the order the simulation first adds them
+    for tile in save.tiles.iter() {
+        let hex = Hex::new(tile.hex.0, tile.hex.1);
+        let mut entity = commands.entity(hex_to_entity[&hex]);
+        entity.insert((
+            tile.elevation,
+            tile.humidity,
+            tile.temperature,
+            tile.biomass
+                .unwrap_or_else(|| Biomass::from(tile.tile_type)),
+            tile.aquifer.unwrap_or_default(),
+            tile.snowpack.unwrap_or_default(),
+            tile.weather.clone(),
+            HexCoordinates(hex),
+            grid.geo_coordinates(hex),
+            Wind::default(),
+            neighbours_of(hex, &grid, &hex_to_entity),
+            tile.pending_humidity.clone(),
+            tile.incoming_overflow.clone(),
+            tile.tile_type,
+        ));
+
+        if let Some(distances) = &tile.distances_from_volcano {
+            entity.insert(distances.clone());
+        }
+        if let Some(higher_neighbours) = &tile.higher_neighbours {
+            entity.insert(HigherNeighbours {
+                ids: by_entity(higher_neighbours),
+            });
+        }
+        if let Some(lower_neighbours) = &tile.lower_neighbours {
+            entity.insert(LowerNeighbours {
+                ids: by_entity(lower_neighbours),
+            });
+        }
+        if tile.tile_type_changed {
+            entity.insert(TileTypeChanged);
+        }
+        if let Some((level, (x, y))) = tile.lake {
+            entity.insert(Lake {
+                level,
+                spill: Hex::new(x, y),
+            });
+        }
+    }
+
+    commands.insert_resource(HexToEntity(hex_to_entity));
+    commands.remove_resource::<SaveFile>();
+}
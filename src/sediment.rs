@@ -0,0 +1,201 @@
+use bevy::prelude::*;
+use serde::Serialize;
+use std::fmt;
+
+use crate::components::{ElevationBundle, IncomingOverflow, LowerNeighbours};
+use crate::world::ErosionAttributes;
+use crate::Epochs;
+
+///////////////////////////////// Stream Power /////////////////////////////////////////
+
+/// Sediment that water flowing off a tile can carry, which grows with both the flow and the
+/// slope it runs down (stream power)
+pub fn transport_capacity(erosion_attributes: &ErosionAttributes, flow: f32, slope: f32) -> f32 {
+    erosion_attributes.erosion_factor * flow * slope.max(0.0)
+}
+
+/// Mean height drop from a tile at `height` to the neighbours its overflow is split between
+pub fn mean_drop(height: f32, lower_neighbours: &LowerNeighbours, split: &[(Entity, f32)]) -> f32 {
+    split
+        .iter()
+        .filter_map(|(neighbour, share)| {
+            lower_neighbours
+                .ids
+                .iter()
+                .find(|(id, _)| id == neighbour)
+                .map(|(_, neighbour_height)| share * (height - neighbour_height))
+        })
+        .sum()
+}
+
+/// Soil and bedrock picked up by water with the given capacity.
+///
+/// Loose soil is carried off first, and only what capacity is left over wears down the bedrock,
+/// slowed down by how hard the rock is. Whatever the water already carries settled on the tile
+/// when it arrived, so a tile whose outflow can carry less than its inflow keeps the difference.
+pub fn entrain(
+    erosion_attributes: &ErosionAttributes,
+    elevation: &ElevationBundle,
+    capacity: f32,
+) -> (f32, f32) {
+    let from_soil = capacity.min(elevation.soil.value);
+    let from_bedrock = ((capacity - from_soil) * erosion_attributes.bedrock_erodibility)
+        .min(elevation.bedrock.value);
+    (from_soil, from_bedrock)
+}
+
+///////////////////////////////// Sediment Budget /////////////////////////////////////////
+
+/// How much rock and soil the planet has and how it moved during the last epoch.
+///
+/// Erosion only moves rock and soil around, so their total should only grow by what the
+/// volcanoes push up.
+#[derive(Debug, Clone, Default, Serialize, Resource)]
+pub struct SedimentBudget {
+    // stores at the end of the last epoch
+    pub bedrock: f32,
+    pub soil: f32,
+
+    // flows during the last epoch
+    pub uplift: f32,
+    pub transported: f32,
+    // bedrock glaciers ground into soil
+    pub glacial_erosion: f32,
+    // sediment that settled on the sea floor
+    pub deposited_at_sea: f32,
+    // change in the total that none of the flows account for, which should stay near 0
+    pub imbalance: f32,
+
+    // total when the epoch started
+    #[serde(skip)]
+    opening_total: f32,
+}
+
+impl SedimentBudget {
+    pub fn total(&self) -> f32 {
+        self.bedrock + self.soil
+    }
+
+    /// Recounts the stores from the tiles, including sediment still on its way to a neighbour
+    fn measure<'a>(
+        &mut self,
+        tiles: impl Iterator<Item = (&'a ElevationBundle, &'a IncomingOverflow)>,
+    ) {
+        self.bedrock = 0.0;
+        self.soil = 0.0;
+        for (elevation, incoming_overflow) in tiles {
+            self.bedrock += elevation.bedrock.value;
+            self.soil += elevation.soil.value + incoming_overflow.soil;
+        }
+    }
+}
+
+/// Takes the first measurement once the tiles are spawned
+pub fn measure_sediment_budget(
+    query: Query<(&ElevationBundle, &IncomingOverflow)>,
+    mut budget: ResMut<SedimentBudget>,
+) {
+    budget.measure(query.iter());
+}
+
+/// Clears last epoch's flows before anything erodes
+pub fn open_sediment_budget(mut debug: ResMut<Epochs>, mut budget: ResMut<SedimentBudget>) {
+    debug.fn_order.push("open_sediment_budget".to_string());
+    *budget = SedimentBudget {
+        bedrock: budget.bedrock,
+        soil: budget.soil,
+        opening_total: budget.total(),
+        ..default()
+    };
+}
+
+/// Recounts the stores and checks them against the uplift once everything has settled
+pub fn close_sediment_budget(
+    mut debug: ResMut<Epochs>,
+    query: Query<(&ElevationBundle, &IncomingOverflow)>,
+    mut budget: ResMut<SedimentBudget>,
+) {
+    debug.fn_order.push("close_sediment_budget".to_string());
+    budget.measure(query.iter());
+    budget.imbalance = budget.total() - budget.opening_total - budget.uplift;
+}
+
+impl fmt::Display for SedimentBudget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Rock and soil: {:.2} total (bedrock {:.2}, soil {:.2}), uplift {:.4}, \
+             transported {:.4}, glacial erosion {:.4}, deposited at sea {:.4}, imbalance {:.4}",
+            self.total(),
+            self.bedrock,
+            self.soil,
+            self.uplift,
+            self.transported,
+            self.glacial_erosion,
+            self.deposited_at_sea,
+            self.imbalance
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::terrain::TileType;
+
+    fn erosion(bedrock_erodibility: f32) -> ErosionAttributes {
+        ErosionAttributes {
+            bedrock_erodibility,
+            ..ErosionAttributes::from(&Config::defaults())
+        }
+    }
+
+    /// A dry tile on 5 of bedrock with `soil` on top
+    fn tile(soil: f32) -> ElevationBundle {
+        let mut elevation = ElevationBundle::from(TileType::Grass, 5.0, 0.0);
+        elevation.soil.value = soil;
+        elevation
+    }
+
+    #[test]
+    fn soil_is_carried_off_before_bedrock() {
+        let erosion = erosion(0.5);
+        assert_eq!(entrain(&erosion, &tile(1.0), 0.25), (0.25, 0.0));
+        assert_eq!(entrain(&erosion, &tile(1.0), 1.0), (1.0, 0.0));
+        // only what is left over once the soil is gone wears down the bedrock
+        assert_eq!(entrain(&erosion, &tile(1.0), 2.0), (1.0, 0.5));
+    }
+
+    #[test]
+    fn bedrock_wears_down_by_its_erodibility() {
+        assert_eq!(entrain(&erosion(0.25), &tile(0.0), 2.0), (0.0, 0.5));
+        assert_eq!(entrain(&erosion(1.0), &tile(0.0), 2.0), (0.0, 2.0));
+        assert_eq!(entrain(&erosion(0.0), &tile(0.0), 2.0), (0.0, 0.0));
+        // there is only so much bedrock to take
+        assert_eq!(entrain(&erosion(1.0), &tile(0.0), 8.0), (0.0, 5.0));
+    }
+
+    #[test]
+    fn water_on_flat_ground_carries_nothing() {
+        let erosion = erosion(0.5);
+        assert_eq!(transport_capacity(&erosion, 3.0, 0.0), 0.0);
+        assert_eq!(transport_capacity(&erosion, 3.0, -1.0), 0.0);
+        assert!(transport_capacity(&erosion, 3.0, 1.0) > 0.0);
+
+        let level = LowerNeighbours {
+            ids: vec![(Entity::from_raw(1), 2.0), (Entity::from_raw(2), 2.0)],
+        };
+        let split = [(Entity::from_raw(1), 0.5), (Entity::from_raw(2), 0.5)];
+        assert_eq!(mean_drop(2.0, &level, &split), 0.0);
+    }
+
+    #[test]
+    fn mean_drop_weighs_each_neighbour_by_its_share() {
+        let lower_neighbours = LowerNeighbours {
+            ids: vec![(Entity::from_raw(1), 9.0), (Entity::from_raw(2), 6.0)],
+        };
+        let split = [(Entity::from_raw(1), 0.75), (Entity::from_raw(2), 0.25)];
+        assert_eq!(mean_drop(10.0, &lower_neighbours, &split), 1.75);
+    }
+}
@@ -0,0 +1,374 @@
+use bevy::prelude::*;
+use hexx::Hex;
+use std::collections::HashMap;
+
+use crate::aquifer::groundwater_flow_system;
+use crate::atmosphere::carbon_cycle_system;
+use crate::biomes::BiomeTable;
+use crate::climate::{energy_balance_system, season_system, wind_system, Season};
+use crate::components::{
+    Aquifer, Biomass, DebugWeatherBundle, DistancesFromVolcano, ElevationBundle, Evaporation,
+    HexCoordinates, Humidity, HumidityReceived, HumiditySent, IncomingOverflow, Neighbours,
+    Overflow, OverflowReceived, PendingHumidityRedistribution, Precipitation, Snowpack,
+    Temperature, Transpiration, Wind,
+};
+use crate::glaciers::{glacier_system, snowfall_system};
+use crate::grid::Grid;
+use crate::hydrology::{fill_basins_system, river_network_system};
+use crate::map_generation::{self, TerrainGenerator};
+use crate::save::{load_grid, SaveFile};
+use crate::sediment::{
+    close_sediment_budget, measure_sediment_budget, open_sediment_budget, SedimentBudget,
+};
+use crate::statistics::EpochStatistics;
+use crate::utils::{RngStream, WorldRng};
+use crate::vegetation::vegetation_system;
+use crate::water_budget::{
+    close_water_budget, measure_water_budget, open_water_budget, WaterBudget,
+};
+use crate::weather_systems::{
+    apply_humidity_redistribution, apply_vulcanism, apply_water_overflow,
+    calculate_neighbour_heights_system, evaporation_system, finish_epoch, morph_terrain_system,
+    precipitation_system, redistribute_humidity_system, redistribute_overflow_system,
+};
+use crate::world::{ElevationAttributes, TemperatureAttributes, WorldAttributes};
+use crate::{Epochs, GameStates, HexToEntity};
+
+// an epoch passes through each of its states once, so it finishes within a few updates
+const MAX_UPDATES_PER_EPOCH: u32 = 16;
+
+////////////////////////////////////////// Plugin /////////////////////////////////////////
+
+/// Spawns the world and runs the epoch state machine without any rendering, input or audio.
+///
+/// Works under `MinimalPlugins` as well as `DefaultPlugins`; the app adds visuals on top.
+pub struct TerraflowSimPlugin {
+    source: WorldSource,
+}
+
+enum WorldSource {
+    Generate {
+        attributes: WorldAttributes,
+        seed: u64,
+    },
+    Load(SaveFile),
+}
+
+impl TerraflowSimPlugin {
+    /// Generates a new world from the given attributes and seed
+    pub fn generate(attributes: WorldAttributes, seed: u64) -> Self {
+        TerraflowSimPlugin {
+            source: WorldSource::Generate { attributes, seed },
+        }
+    }
+
+    /// Continues a previously saved world
+    pub fn load(save: SaveFile) -> Self {
+        TerraflowSimPlugin {
+            source: WorldSource::Load(save),
+        }
+    }
+}
+
+impl Plugin for TerraflowSimPlugin {
+    fn build(&self, app: &mut App) {
+        let world = match &self.source {
+            WorldSource::Generate { attributes, seed } => {
+                app.insert_resource(WorldRng::new(*seed))
+                    .insert_resource(Epochs::default())
+                    .insert_resource(WaterBudget::default())
+                    .insert_resource(attributes.temperature.atmosphere.composition.clone());
+                attributes.clone()
+            }
+            WorldSource::Load(save) => {
+                app.insert_resource(save.rng.clone())
+                    .insert_resource(Epochs {
+                        epochs: save.epochs,
+                        ..default()
+                    })
+                    .insert_resource(WaterBudget::new(save.ocean_balance))
+                    .insert_resource(save.atmosphere.clone().unwrap_or_else(|| {
+                        save.attributes.temperature.atmosphere.composition.clone()
+                    }))
+                    .insert_resource(save.clone());
+                save.attributes.clone()
+            }
+        };
+        let season = Season::at(
+            &world.temperature.orbit,
+            app.world.resource::<Epochs>().epochs,
+        );
+
+        app
+            // World Attributes
+            .insert_resource(world.elevation.clone()) // ElevationAttributes
+            .insert_resource(world.erosion.clone()) // ErosionAttributes
+            .insert_resource(world.ecosystem.clone()) // EcosystemAttributes
+            .insert_resource(world.temperature.clone()) // TemperatureAttributes
+            .insert_resource(world.map.clone()) // MapAttributes
+            .insert_resource(season)
+            .insert_resource(SedimentBudget::default())
+            .insert_resource(world.biomes.clone()) // BiomeTable
+            .insert_resource(world.rules.clone()) // TransitionRules
+            .insert_resource(Grid::new(&world.map))
+            .insert_resource(world)
+            .add_state::<GameStates>()
+            .add_systems(
+                Startup,
+                (
+                    setup_grid.run_if(not(resource_exists::<SaveFile>())),
+                    load_grid.run_if(resource_exists::<SaveFile>()),
+                ),
+            )
+            .add_systems(PostStartup, (measure_water_budget, measure_sediment_budget))
+            // initial weather phase
+            // systems within a phase are chained so that every run applies them in the same order
+            .add_systems(
+                OnEnter(GameStates::EpochStart),
+                (
+                    open_water_budget,
+                    open_sediment_budget,
+                    season_system,
+                    energy_balance_system,
+                    wind_system,
+                    precipitation_system,
+                    snowfall_system,
+                    evaporation_system,
+                    calculate_neighbour_heights_system,
+                )
+                    .chain(),
+            )
+            // calculate neighbour effects
+            .add_systems(
+                OnExit(GameStates::EpochStart),
+                (redistribute_humidity_system, redistribute_overflow_system).chain(),
+            )
+            // apply effects on neighbours
+            .add_systems(
+                OnEnter(GameStates::EpochRunning),
+                (
+                    apply_water_overflow,
+                    glacier_system,
+                    groundwater_flow_system,
+                    fill_basins_system,
+                    river_network_system,
+                    apply_humidity_redistribution,
+                    apply_vulcanism,
+                    carbon_cycle_system,
+                )
+                    .chain(),
+            )
+            // update terrain and finish the epoch
+            .add_systems(
+                OnExit(GameStates::EpochRunning),
+                (
+                    vegetation_system,
+                    morph_terrain_system,
+                    close_water_budget,
+                    close_sediment_budget,
+                )
+                    .chain(),
+            )
+            .add_systems(OnEnter(GameStates::EpochFinish), finish_epoch);
+    }
+}
+
+/// Hex grid setup
+pub fn setup_grid(
+    mut commands: Commands,
+    biomes: Res<BiomeTable>,
+    elevation_attributes: Res<ElevationAttributes>,
+    temperature_attributes: Res<TemperatureAttributes>,
+    grid: Res<Grid>,
+    mut world_rng: ResMut<WorldRng>,
+) {
+    // generate altitude and derive temperature from that
+    let map_generation::Terrain {
+        altitude_map,
+        volcano_hexes,
+    } = elevation_attributes.terrain_generator.generate(
+        &elevation_attributes,
+        &grid,
+        world_rng.stream(RngStream::Generation),
+    );
+    let temperature_map =
+        map_generation::generate_temperature_map(&temperature_attributes, &grid, &altitude_map);
+    let moisture_map =
+        map_generation::generate_moisture_map(&elevation_attributes, &grid, &altitude_map);
+
+    let mut hex_to_entity = HashMap::new();
+
+    // Spawn tiles
+    for hex in grid.tiles().iter().cloned() {
+        let altitude = *altitude_map.get(&hex).unwrap();
+        let temperature = *temperature_map.get(&hex).unwrap();
+        let geo_coordinates = grid.geo_coordinates(hex);
+
+        // spawn tile from the biome its climate and altitude fall in
+        let tile_type = biomes.spawn_tile(
+            temperature,
+            moisture_map[&hex],
+            altitude,
+            &elevation_attributes,
+            world_rng.stream(RngStream::TileSpawn),
+        );
+        let amount_below_sea_level = (elevation_attributes.sea_level - altitude).max(0.0);
+
+        // create terrain entity
+        let id = commands
+            .spawn((
+                ElevationBundle::from(tile_type, altitude, amount_below_sea_level),
+                Humidity::from(tile_type),
+                Temperature { value: temperature },
+                Biomass::from(tile_type),
+                Aquifer::default(),
+                Snowpack::default(),
+                DebugWeatherBundle {
+                    evaporation: Evaporation { value: 0.0 },
+                    transpiration: Transpiration { value: 0.0 },
+                    precipitation: Precipitation { value: 0.0 },
+                    overflow: Overflow {
+                        water: 0.0,
+                        soil: 0.0,
+                    },
+                    overflow_received: OverflowReceived {
+                        water: 0.0,
+                        soil: 0.0,
+                    },
+                    humidity_received: HumidityReceived { value: 0.0 },
+                    humidity_sent: HumiditySent { value: 0.0 },
+                    overflow_split: vec![],
+                },
+                HexCoordinates(hex),
+                geo_coordinates,
+                Wind::default(),
+                Neighbours { ids: vec![] }, // populate once all entities are spawned
+                PendingHumidityRedistribution {
+                    value: 0.0,
+                    rain: 0.0,
+                },
+                IncomingOverflow {
+                    water: 0.0,
+                    soil: 0.0,
+                },
+                tile_type,
+            ))
+            .id();
+
+        hex_to_entity.insert(hex, id);
+    }
+
+    let distances_to_volcanoes =
+        map_generation::get_distances_from_volcanos(&elevation_attributes, &grid, &volcano_hexes);
+
+    // Populate `Neighbours` component for each entity
+    for hex in grid.tiles().iter().cloned() {
+        let entity_id = hex_to_entity[&hex];
+        commands
+            .entity(entity_id)
+            .insert(neighbours_of(hex, &grid, &hex_to_entity));
+
+        match distances_to_volcanoes.get(&hex) {
+            Some(distances) => {
+                commands
+                    .entity(entity_id)
+                    .insert(DistancesFromVolcano(distances.to_vec()));
+            }
+            None => continue,
+        }
+    }
+
+    commands.insert_resource(HexToEntity(hex_to_entity));
+}
+
+/// Every spawned tile adjacent to the given hex
+pub fn neighbours_of(hex: Hex, grid: &Grid, hex_to_entity: &HashMap<Hex, Entity>) -> Neighbours {
+    let ids = grid
+        .neighbours(hex)
+        .iter()
+        .filter_map(|neighbour_hex| hex_to_entity.get(neighbour_hex))
+        .cloned()
+        .collect::<Vec<Entity>>();
+
+    Neighbours { ids }
+}
+
+////////////////////////////////////////// Headless World /////////////////////////////////////////
+
+/// A self-contained simulation that can be advanced from plain Rust, with no window or GPU.
+pub struct World {
+    app: App,
+}
+
+impl World {
+    pub fn new(attributes: WorldAttributes, seed: u64) -> Self {
+        Self::with_plugin(TerraflowSimPlugin::generate(attributes, seed))
+    }
+
+    pub fn from_save(save: SaveFile) -> Self {
+        Self::with_plugin(TerraflowSimPlugin::load(save))
+    }
+
+    fn with_plugin(plugin: TerraflowSimPlugin) -> Self {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins).add_plugins(plugin);
+
+        // runs the startup systems that spawn the tiles
+        app.update();
+
+        World { app }
+    }
+
+    /// Runs a single full epoch
+    pub fn step(&mut self) {
+        let target = self.epochs() + 1;
+
+        self.app.world.resource_mut::<Epochs>().fn_order.clear();
+        self.app
+            .world
+            .resource_mut::<NextState<GameStates>>()
+            .set(GameStates::EpochStart);
+
+        let mut updates = 0;
+        while self.epochs() < target {
+            assert!(
+                updates < MAX_UPDATES_PER_EPOCH,
+                "epoch {} didn't finish within {} updates",
+                target,
+                MAX_UPDATES_PER_EPOCH
+            );
+            self.app.update();
+            updates += 1;
+        }
+    }
+
+    /// Runs the given number of epochs
+    pub fn run(&mut self, epochs: u32) {
+        for _ in 0..epochs {
+            self.step();
+        }
+    }
+
+    /// Number of epochs completed so far
+    pub fn epochs(&self) -> u32 {
+        self.app.world.resource::<Epochs>().epochs
+    }
+
+    pub fn statistics(&mut self) -> EpochStatistics {
+        let epoch = self.epochs();
+        EpochStatistics::collect(&mut self.app.world, epoch)
+    }
+
+    pub fn save(&mut self) -> SaveFile {
+        SaveFile::capture(&mut self.app.world)
+    }
+
+    /// The underlying ECS world, for reading tiles and resources
+    pub fn ecs(&self) -> &bevy::ecs::world::World {
+        &self.app.world
+    }
+
+    pub fn ecs_mut(&mut self) -> &mut bevy::ecs::world::World {
+        &mut self.app.world
+    }
+}
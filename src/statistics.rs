@@ -0,0 +1,229 @@
+use bevy::prelude::*;
+use serde::Serialize;
+use strum::{EnumCount, IntoEnumIterator};
+
+use crate::atmosphere::Atmosphere;
+use crate::climate::Season;
+use crate::components::{
+    Aquifer, Biomass, DebugWeatherBundle, ElevationBundle, Humidity, Lake, River, Temperature,
+};
+use crate::sediment::SedimentBudget;
+use crate::terrain::TileType;
+use crate::water_budget::WaterBudget;
+use crate::world::TemperatureAttributes;
+
+/// Summary of the whole world after an epoch, written out by batch runs
+#[derive(Debug, Clone, Serialize)]
+pub struct EpochStatistics {
+    pub epoch: u32,
+    pub mean_temperature: f32,
+    pub mean_humidity: f32,
+    // over the land tiles only
+    pub mean_biomass: f32,
+    pub total_bedrock: f32,
+    pub total_soil: f32,
+    pub total_water: f32,
+    pub total_evaporation: f32,
+    pub total_transpiration: f32,
+    pub total_precipitation: f32,
+    pub sub_solar_latitude: f32,
+    pub atmosphere: Atmosphere,
+    pub greenhouse_forcing: f32,
+    pub water_budget: WaterBudget,
+    pub sediment_budget: SedimentBudget,
+    pub lake_tiles: u32,
+    pub river_tiles: u32,
+    // largest discharge of any river
+    pub peak_discharge: f32,
+    // tiles where water rose out of a full aquifer
+    pub spring_tiles: u32,
+    // indexed by `TileType`
+    pub tile_counts: Vec<u32>,
+}
+
+impl EpochStatistics {
+    pub fn collect(world: &mut World, epoch: u32) -> Self {
+        let mut statistics = EpochStatistics {
+            epoch,
+            mean_temperature: 0.0,
+            mean_humidity: 0.0,
+            mean_biomass: 0.0,
+            total_bedrock: 0.0,
+            total_soil: 0.0,
+            total_water: 0.0,
+            total_evaporation: 0.0,
+            total_transpiration: 0.0,
+            total_precipitation: 0.0,
+            sub_solar_latitude: world
+                .get_resource::<Season>()
+                .map_or(0.0, |season| season.sub_solar_latitude),
+            atmosphere: world
+                .get_resource::<Atmosphere>()
+                .cloned()
+                .unwrap_or_default(),
+            greenhouse_forcing: 0.0,
+            water_budget: world
+                .get_resource::<WaterBudget>()
+                .cloned()
+                .unwrap_or_default(),
+            sediment_budget: world
+                .get_resource::<SedimentBudget>()
+                .cloned()
+                .unwrap_or_default(),
+            lake_tiles: 0,
+            river_tiles: 0,
+            peak_discharge: 0.0,
+            spring_tiles: 0,
+            tile_counts: vec![0; TileType::COUNT],
+        };
+
+        if let Some(temperature_attributes) = world.get_resource::<TemperatureAttributes>() {
+            statistics.greenhouse_forcing = statistics
+                .atmosphere
+                .greenhouse_forcing(&temperature_attributes.atmosphere);
+        }
+
+        let mut query = world.query::<(
+            &ElevationBundle,
+            &Humidity,
+            &Temperature,
+            &DebugWeatherBundle,
+            &Biomass,
+            &TileType,
+        )>();
+
+        let mut num_tiles = 0;
+        let mut num_land_tiles = 0;
+        for (elevation, humidity, temperature, weather, biomass, tile_type) in query.iter(world) {
+            statistics.mean_temperature += temperature.value;
+            statistics.mean_humidity += humidity.value;
+            statistics.total_bedrock += elevation.bedrock.value;
+            statistics.total_soil += elevation.soil.value;
+            statistics.total_water += elevation.water.value;
+            statistics.total_evaporation += weather.evaporation.value;
+            statistics.total_transpiration += weather.transpiration.value;
+            statistics.total_precipitation += weather.precipitation.value;
+            statistics.tile_counts[*tile_type as usize] += 1;
+            num_tiles += 1;
+            if *tile_type != TileType::Ocean {
+                statistics.mean_biomass += biomass.value;
+                num_land_tiles += 1;
+            }
+        }
+
+        statistics.lake_tiles = world.query_filtered::<(), With<Lake>>().iter(world).count() as u32;
+        for river in world.query::<&River>().iter(world) {
+            statistics.river_tiles += 1;
+            statistics.peak_discharge = statistics.peak_discharge.max(river.discharge);
+        }
+        statistics.spring_tiles = world
+            .query::<&Aquifer>()
+            .iter(world)
+            .filter(|aquifer| aquifer.spring > 0.0)
+            .count() as u32;
+
+        if num_tiles > 0 {
+            statistics.mean_temperature /= num_tiles as f32;
+            statistics.mean_humidity /= num_tiles as f32;
+        }
+        if num_land_tiles > 0 {
+            statistics.mean_biomass /= num_land_tiles as f32;
+        }
+
+        statistics
+    }
+
+    pub fn csv_header() -> String {
+        let mut columns = vec![
+            "epoch".to_string(),
+            "mean_temperature".to_string(),
+            "mean_humidity".to_string(),
+            "mean_biomass".to_string(),
+            "total_bedrock".to_string(),
+            "total_soil".to_string(),
+            "total_water".to_string(),
+            "total_evaporation".to_string(),
+            "total_transpiration".to_string(),
+            "total_precipitation".to_string(),
+            "sub_solar_latitude".to_string(),
+            "co2".to_string(),
+            "o2".to_string(),
+            "n2".to_string(),
+            "ch4".to_string(),
+            "pressure".to_string(),
+            "greenhouse_forcing".to_string(),
+            "ocean_water".to_string(),
+            "atmosphere_water".to_string(),
+            "surface_water".to_string(),
+            "ground_water".to_string(),
+            "aquifer_water".to_string(),
+            "ice_water".to_string(),
+            "infiltration".to_string(),
+            "spring_discharge".to_string(),
+            "snowfall".to_string(),
+            "meltwater".to_string(),
+            "runoff_to_ocean".to_string(),
+            "water_created".to_string(),
+            "water_lost".to_string(),
+            "water_imbalance".to_string(),
+            "uplift".to_string(),
+            "sediment_transported".to_string(),
+            "glacial_erosion".to_string(),
+            "sediment_deposited_at_sea".to_string(),
+            "sediment_imbalance".to_string(),
+            "lake_tiles".to_string(),
+            "river_tiles".to_string(),
+            "peak_discharge".to_string(),
+            "spring_tiles".to_string(),
+        ];
+        columns.extend(TileType::iter().map(|tile_type| format!("{:?}", tile_type)));
+        columns.join(",")
+    }
+
+    pub fn csv_row(&self) -> String {
+        let mut columns = vec![
+            self.epoch.to_string(),
+            self.mean_temperature.to_string(),
+            self.mean_humidity.to_string(),
+            self.mean_biomass.to_string(),
+            self.total_bedrock.to_string(),
+            self.total_soil.to_string(),
+            self.total_water.to_string(),
+            self.total_evaporation.to_string(),
+            self.total_transpiration.to_string(),
+            self.total_precipitation.to_string(),
+            self.sub_solar_latitude.to_string(),
+            self.atmosphere.co2.to_string(),
+            self.atmosphere.o2.to_string(),
+            self.atmosphere.n2.to_string(),
+            self.atmosphere.ch4.to_string(),
+            self.atmosphere.pressure().to_string(),
+            self.greenhouse_forcing.to_string(),
+            self.water_budget.ocean.to_string(),
+            self.water_budget.atmosphere.to_string(),
+            self.water_budget.surface.to_string(),
+            self.water_budget.ground.to_string(),
+            self.water_budget.aquifer.to_string(),
+            self.water_budget.ice.to_string(),
+            self.water_budget.infiltration.to_string(),
+            self.water_budget.spring_discharge.to_string(),
+            self.water_budget.snowfall.to_string(),
+            self.water_budget.meltwater.to_string(),
+            self.water_budget.runoff_to_ocean.to_string(),
+            self.water_budget.created.to_string(),
+            self.water_budget.lost.to_string(),
+            self.water_budget.imbalance.to_string(),
+            self.sediment_budget.uplift.to_string(),
+            self.sediment_budget.transported.to_string(),
+            self.sediment_budget.glacial_erosion.to_string(),
+            self.sediment_budget.deposited_at_sea.to_string(),
+            self.sediment_budget.imbalance.to_string(),
+            self.lake_tiles.to_string(),
+            self.river_tiles.to_string(),
+            self.peak_discharge.to_string(),
+            self.spring_tiles.to_string(),
+        ];
+        columns.extend(self.tile_counts.iter().map(|count| count.to_string()));
+        columns.join(",")
+    }
+}
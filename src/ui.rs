@@ -7,43 +7,84 @@ use bevy_egui::{
 };
 use egui::Color32;
 
-use crate::components::{
-    DebugWeatherBundle, ElevationBundle, Evaporation, HexCoordinates, Humidity, HumidityReceived,
-    HumiditySent, Overflow, OverflowReceived, Precipitation, Temperature,
+use terraflow::atmosphere::Atmosphere;
+use terraflow::components::{
+    Aquifer, Biomass, DebugWeatherBundle, ElevationBundle, Evaporation, GeoCoordinates,
+    HexCoordinates, Humidity, HumidityReceived, HumiditySent, Lake, OutgoingOverflow, Overflow,
+    OverflowReceived, Precipitation, River, Snowpack, Temperature, Transpiration, Wind,
 };
-use crate::terrain::TileType;
+use terraflow::terrain::TileType;
+use terraflow::world::TemperatureAttributes;
 
 pub fn terrain_callback(
     event: Listener<Pointer<Click>>,
     query: Query<(
         Entity,
         &HexCoordinates,
+        &GeoCoordinates,
         &ElevationBundle,
         &Humidity,
         &Temperature,
+        // grouped to stay within the 15 components a query can fetch
+        (&Biomass, &Aquifer, &Snowpack),
+        &Wind,
         &TileType,
         &DebugWeatherBundle,
+        Option<&Lake>,
+        Option<&River>,
         &Children,
     )>,
     mut selected_tile: ResMut<SelectedTile>,
 ) {
-    for (entity, hex_coordinates, elevation, humidity, temperature, tile_type, weather, _parent) in
-        query.iter()
+    for (
+        entity,
+        hex_coordinates,
+        geo_coordinates,
+        elevation,
+        humidity,
+        temperature,
+        (biomass, aquifer, snowpack),
+        wind,
+        tile_type,
+        weather,
+        lake,
+        river,
+        _parent,
+    ) in query.iter()
     {
         if entity == event.listener() {
             println!("Selected tile: {:?}", entity);
             selected_tile.entity = Some(entity);
             selected_tile.hex_coordinates = Some(hex_coordinates.clone());
+            selected_tile.geo_coordinates = Some(*geo_coordinates);
             selected_tile.elevation = Some(*elevation);
             selected_tile.humidity = Some(*humidity);
             selected_tile.temperature = Some(*temperature);
+            selected_tile.biomass = Some(*biomass);
+            selected_tile.aquifer = Some(*aquifer);
+            selected_tile.snowpack = Some(*snowpack);
+            selected_tile.wind = Some(*wind);
             selected_tile.evaporation = Some(weather.evaporation);
+            selected_tile.transpiration = Some(weather.transpiration);
             selected_tile.precipitation = Some(weather.precipitation);
             selected_tile.humidity_received = Some(weather.humidity_received);
             selected_tile.humidity_sent = Some(weather.humidity_sent);
             selected_tile.overflow = Some(weather.overflow);
+            selected_tile.overflow_split = weather
+                .overflow_split
+                .iter()
+                .filter_map(|(neighbour, outflow)| {
+                    let (_, neighbour_hex, ..) = query.get(*neighbour).ok()?;
+                    Some((neighbour_hex.clone(), outflow.clone()))
+                })
+                .collect();
             selected_tile.overflow_received = Some(weather.overflow_received);
             selected_tile.tile_type = Some(*tile_type);
+            selected_tile.lake = lake.copied();
+            selected_tile.river = river.and_then(|river| {
+                let (_, downstream_hex, ..) = query.get(river.downstream).ok()?;
+                Some((*river, downstream_hex.clone()))
+            });
             break;
         }
     }
@@ -53,16 +94,25 @@ pub fn terrain_callback(
 pub struct SelectedTile {
     pub entity: Option<Entity>,
     pub hex_coordinates: Option<HexCoordinates>,
+    pub geo_coordinates: Option<GeoCoordinates>,
     pub elevation: Option<ElevationBundle>,
     pub humidity: Option<Humidity>,
     pub temperature: Option<Temperature>,
+    pub biomass: Option<Biomass>,
+    pub aquifer: Option<Aquifer>,
+    pub snowpack: Option<Snowpack>,
+    pub wind: Option<Wind>,
     pub evaporation: Option<Evaporation>,
+    pub transpiration: Option<Transpiration>,
     pub precipitation: Option<Precipitation>,
     pub overflow: Option<Overflow>,
+    pub overflow_split: Vec<(HexCoordinates, OutgoingOverflow)>,
     pub overflow_received: Option<OverflowReceived>,
     pub tile_type: Option<TileType>,
     pub humidity_received: Option<HumidityReceived>,
     pub humidity_sent: Option<HumiditySent>,
+    pub lake: Option<Lake>,
+    pub river: Option<(River, HexCoordinates)>,
 }
 
 pub fn terrain_details(mut egui_contexts: EguiContexts, selected_tile: Res<SelectedTile>) {
@@ -89,6 +139,12 @@ pub fn terrain_details(mut egui_contexts: EguiContexts, selected_tile: Res<Selec
                             ui.label(format!("{}", hex_coordinates));
                         });
                     }
+                    if let Some(geo_coordinates) = &selected_tile.geo_coordinates {
+                        ui.horizontal(|ui| {
+                            ui.label("Location:");
+                            ui.label(format!("{}", geo_coordinates));
+                        });
+                    }
                     if let Some(elevation) = &selected_tile.elevation {
                         ui.horizontal(|ui| {
                             ui.label("water elevation");
@@ -102,6 +158,10 @@ pub fn terrain_details(mut egui_contexts: EguiContexts, selected_tile: Res<Selec
                             ui.label("bedrock elevation");
                             ui.colored_label(Color32::GRAY, format!("{}", elevation.bedrock));
                         });
+                        ui.horizontal(|ui| {
+                            ui.label("ice elevation");
+                            ui.colored_label(Color32::LIGHT_BLUE, format!("{}", elevation.ice));
+                        });
                     }
                     if let Some(humidity) = &selected_tile.humidity {
                         ui.horizontal(|ui| {
@@ -116,12 +176,57 @@ pub fn terrain_details(mut egui_contexts: EguiContexts, selected_tile: Res<Selec
                             ui.label(format!("{}", temperature));
                         });
                     }
+                    if let Some(biomass) = &selected_tile.biomass {
+                        ui.horizontal(|ui| {
+                            ui.label("Biomass:");
+                            ui.colored_label(Color32::DARK_GREEN, format!("{}", biomass));
+                        });
+                    }
+                    if let Some(aquifer) = &selected_tile.aquifer {
+                        ui.horizontal(|ui| {
+                            ui.label("Aquifer:");
+                            ui.colored_label(Color32::BLUE, format!("{}", aquifer));
+                        });
+                    }
+                    if let Some(snowpack) = &selected_tile.snowpack {
+                        ui.horizontal(|ui| {
+                            ui.label("Snowpack:");
+                            ui.colored_label(Color32::WHITE, format!("{}", snowpack));
+                        });
+                    }
+                    if let Some(wind) = &selected_tile.wind {
+                        ui.horizontal(|ui| {
+                            ui.label("Wind:");
+                            ui.label(format!("{}", wind));
+                        });
+                    }
+                    if let Some(lake) = &selected_tile.lake {
+                        ui.horizontal(|ui| {
+                            ui.label("Lake:");
+                            ui.colored_label(Color32::BLUE, format!("{}", lake));
+                        });
+                    }
+                    if let Some((river, downstream_hex)) = &selected_tile.river {
+                        ui.horizontal(|ui| {
+                            ui.label("River:");
+                            ui.colored_label(
+                                Color32::BLUE,
+                                format!("{}, flows to {}", river, downstream_hex),
+                            );
+                        });
+                    }
                     if let Some(evaporation) = &selected_tile.evaporation {
                         ui.horizontal(|ui| {
                             ui.label("Evaporation:");
                             ui.label(format!("{}", evaporation));
                         });
                     }
+                    if let Some(transpiration) = &selected_tile.transpiration {
+                        ui.horizontal(|ui| {
+                            ui.label("Transpiration:");
+                            ui.label(format!("{}", transpiration));
+                        });
+                    }
                     if let Some(precipitation) = &selected_tile.precipitation {
                         ui.horizontal(|ui| {
                             ui.label("Precipitation:");
@@ -134,6 +239,12 @@ pub fn terrain_details(mut egui_contexts: EguiContexts, selected_tile: Res<Selec
                             ui.label(format!("{}", overflow));
                         });
                     }
+                    for (neighbour_hex, outflow) in &selected_tile.overflow_split {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("  to {}:", neighbour_hex));
+                            ui.label(format!("{}", outflow));
+                        });
+                    }
                     if let Some(overflow_received) = &selected_tile.overflow_received {
                         ui.horizontal(|ui| {
                             ui.label("Overflow Received:");
@@ -161,3 +272,41 @@ pub fn terrain_details(mut egui_contexts: EguiContexts, selected_tile: Res<Selec
         });
     });
 }
+
+pub fn atmosphere_details(
+    mut egui_contexts: EguiContexts,
+    atmosphere: Res<Atmosphere>,
+    temperature_attributes: Res<TemperatureAttributes>,
+) {
+    egui::Window::new("Atmosphere").show(egui_contexts.ctx_mut(), |ui| {
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                ui.label("Pressure:");
+                ui.label(format!("{:.2} kPa", atmosphere.pressure()));
+            });
+            ui.separator();
+            for (gas, pressure) in [
+                ("CO2", atmosphere.co2),
+                ("O2", atmosphere.o2),
+                ("N2", atmosphere.n2),
+                ("CH4", atmosphere.ch4),
+            ] {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}:", gas));
+                    ui.label(format!("{:.5} kPa", pressure));
+                });
+            }
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Greenhouse forcing:");
+                ui.colored_label(
+                    Color32::RED,
+                    format!(
+                        "{:.2} W/m^2",
+                        atmosphere.greenhouse_forcing(&temperature_attributes.atmosphere)
+                    ),
+                );
+            });
+        });
+    });
+}
@@ -1,37 +1,89 @@
 use crate::components::LowerNeighbours;
 use bevy::ecs::entity::Entity;
+use bevy::prelude::Resource;
 
 use super::terrain::TileType;
 
-use rand::{prelude::SliceRandom, Rng};
+use rand::{prelude::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use strum::{EnumCount, IntoEnumIterator};
+use strum_macros::{EnumCount, EnumIter};
+
+// environment variable used to pick a seed when no flag is given
+pub const SEED_ENV_VAR: &str = "TERRAFLOW_SEED";
 
 ///////////////////////////////////////// Randomness ////////////////////////////////////////////////
 ///
+/// Independent random streams, one per consumer of randomness.
+///
+/// Each stream is derived from the world seed, so draws made by one system never shift
+/// the numbers seen by another, regardless of the order the systems run in.
+#[derive(Clone, Copy, EnumCount, EnumIter, Debug, PartialEq)]
+pub enum RngStream {
+    Generation,
+    TileSpawn,
+    Vulcanism,
+    Overflow,
+    Morph,
+}
+
+/// The single source of randomness for world generation and the simulation.
+///
+/// A given seed and config reproduce the same map and the same epoch-by-epoch evolution.
+#[derive(Debug, Clone, Resource, Serialize, Deserialize)]
+pub struct WorldRng {
+    seed: u64,
+    streams: Vec<ChaCha8Rng>,
+}
+
+impl WorldRng {
+    pub fn new(seed: u64) -> Self {
+        let streams = RngStream::iter()
+            .map(|stream| {
+                let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                rng.set_stream(stream as u64);
+                rng
+            })
+            .collect::<Vec<ChaCha8Rng>>();
+        assert_eq!(streams.len(), RngStream::COUNT);
+
+        Self { seed, streams }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn stream(&mut self, stream: RngStream) -> &mut ChaCha8Rng {
+        &mut self.streams[stream as usize]
+    }
+}
+
 pub trait RandomSelection<T> {
-    fn pick_random(&self) -> T;
+    fn pick_random<R: Rng + ?Sized>(&self, rng: &mut R) -> T;
 }
 
 impl RandomSelection<bool> for f32 {
-    fn pick_random(&self) -> bool {
-        rand::thread_rng().gen::<f32>() < *self
+    fn pick_random<R: Rng + ?Sized>(&self, rng: &mut R) -> bool {
+        rng.gen::<f32>() < *self
     }
 }
 
 impl RandomSelection<u32> for Vec<u32> {
-    fn pick_random(&self) -> u32 {
-        *self.choose(&mut rand::thread_rng()).unwrap()
+    fn pick_random<R: Rng + ?Sized>(&self, rng: &mut R) -> u32 {
+        *self.choose(rng).unwrap()
     }
 }
 
 impl RandomSelection<TileType> for Vec<TileType> {
-    fn pick_random(&self) -> TileType {
-        *self.choose(&mut rand::thread_rng()).unwrap()
+    fn pick_random<R: Rng + ?Sized>(&self, rng: &mut R) -> TileType {
+        *self.choose(rng).unwrap()
     }
 }
 
 impl RandomSelection<TileType> for Vec<(TileType, f32)> {
-    fn pick_random(&self) -> TileType {
-        let mut rng = rand::thread_rng();
+    fn pick_random<R: Rng + ?Sized>(&self, rng: &mut R) -> TileType {
         let total_weight: f32 = self.iter().map(|(_, weight)| weight).sum();
         let mut random_weight = rng.gen_range(0.0..total_weight);
 
@@ -45,7 +97,10 @@ impl RandomSelection<TileType> for Vec<(TileType, f32)> {
     }
 }
 
-pub fn get_lowest_neighbour(lower_neighbours: &LowerNeighbours) -> Entity {
+pub fn get_lowest_neighbour<R: Rng + ?Sized>(
+    lower_neighbours: &LowerNeighbours,
+    rng: &mut R,
+) -> Entity {
     let mut lowest_neighbours = Vec::new();
     let mut lowest_height = f32::MAX;
 
@@ -60,7 +115,7 @@ pub fn get_lowest_neighbour(lower_neighbours: &LowerNeighbours) -> Entity {
     }
 
     let receiver_index = if !lowest_neighbours.is_empty() {
-        lowest_neighbours.choose(&mut rand::thread_rng()).unwrap()
+        lowest_neighbours.choose(rng).unwrap()
     } else {
         panic!("No lower neighbours found!");
     };
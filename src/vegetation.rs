@@ -0,0 +1,186 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::components::{Biomass, ElevationBundle, Neighbours, Temperature};
+use crate::config::Config;
+use crate::terrain::TileType;
+use crate::world::EcosystemAttributes;
+use crate::Epochs;
+
+// dormant seeds in the soil, which let bare ground green up once it gets water
+const SEED_BANK: f32 = 0.01;
+
+///////////////////////////////// Vegetation /////////////////////////////////////////
+
+/// How vegetation grows, spreads and dies back, set by the `vegetation` config key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VegetationAttributes {
+    // share of the free room that fills up every epoch under ideal conditions
+    pub growth_rate: f32,
+    // soil depth that can carry the most vegetation, shallower soil carries proportionally less
+    pub full_cover_soil: f32,
+    // share of the biomass lost every epoch to drought or frost
+    pub dieback_rate: f32,
+    // share of the neighbours' mean biomass that takes root on a tile every epoch
+    pub seed_dispersal: f32,
+    // soil saturation below which plants dry out
+    pub drought_moisture: f32,
+    // degrees below which plants freeze, at which they thrive most, and above which they wilt
+    pub frost_temperature: f32,
+    pub optimum_temperature: f32,
+    pub heat_temperature: f32,
+    // share of the soil water fully grown vegetation releases into the air every epoch, at the
+    // base temperature and in full sun.
+    #[serde(default)]
+    pub transpiration_factor: f32,
+}
+
+impl Default for VegetationAttributes {
+    fn default() -> Self {
+        Config::defaults().vegetation
+    }
+}
+
+impl VegetationAttributes {
+    /// Most biomass a tile can carry, which grows with the soil depth and how well the
+    /// temperature suits plants
+    pub fn capacity(&self, soil: f32, temperature: f32) -> f32 {
+        (soil / self.full_cover_soil).clamp(0.0, 1.0) * self.warmth(temperature)
+    }
+
+    /// 1 at the optimum temperature, falling off linearly to 0 at the frost and heat limits
+    pub fn warmth(&self, temperature: f32) -> f32 {
+        let warmth = if temperature < self.optimum_temperature {
+            (temperature - self.frost_temperature)
+                / (self.optimum_temperature - self.frost_temperature)
+        } else {
+            (self.heat_temperature - temperature)
+                / (self.heat_temperature - self.optimum_temperature)
+        };
+        warmth.clamp(0.0, 1.0)
+    }
+}
+
+/// Grows every tile's biomass logistically towards what its soil and temperature can carry, as
+/// fast as its water allows, lets it spread from the neighbours and dies it back in droughts and
+/// frost. Biomass above what the tile can carry, after it cools or its soil washes away, thins out
+/// gradually the same way.
+pub fn vegetation_system(
+    mut debug: ResMut<Epochs>,
+    mut query: Query<(
+        Entity,
+        &mut Biomass,
+        &ElevationBundle,
+        &Temperature,
+        &TileType,
+        &Neighbours,
+    )>,
+    ecosystem_attributes: Res<EcosystemAttributes>,
+) {
+    debug.fn_order.push("vegetation_system".to_string());
+    let vegetation = &ecosystem_attributes.vegetation;
+
+    // seeds come from the neighbours as they were before this epoch
+    let biomass_before: HashMap<Entity, f32> = query
+        .iter()
+        .map(|(entity, biomass, ..)| (entity, biomass.value))
+        .collect();
+
+    for (_entity, mut biomass, elevation, temperature, tile_type, neighbours) in query.iter_mut() {
+        if *tile_type == TileType::Ocean || elevation.soil.value <= 0.0 {
+            biomass.value = 0.0;
+            continue;
+        }
+
+        let capacity = vegetation.capacity(elevation.soil.value, temperature.value);
+        let moisture = (elevation.water.value / elevation.soil.value).min(1.0);
+        // share of what the tile can carry still unused, negative when it carries too much so
+        // that at most all of it thins out at once
+        let room = if capacity > 0.0 {
+            (1.0 - biomass.value / capacity).max(-1.0)
+        } else {
+            -1.0
+        };
+
+        let growth = vegetation.growth_rate * moisture * biomass.value.max(SEED_BANK) * room;
+        let neighbour_biomass: Vec<f32> = neighbours
+            .ids
+            .iter()
+            .filter_map(|id| biomass_before.get(id).copied())
+            .collect();
+        let seeds = if neighbour_biomass.is_empty() {
+            0.0
+        } else {
+            let mean = neighbour_biomass.iter().sum::<f32>() / neighbour_biomass.len() as f32;
+            vegetation.seed_dispersal * moisture * mean * room.max(0.0)
+        };
+        let dieback = if moisture < vegetation.drought_moisture
+            || temperature.value < vegetation.frost_temperature
+        {
+            vegetation.dieback_rate * biomass.value
+        } else {
+            0.0
+        };
+
+        biomass.value = (biomass.value + growth + seeds - dieback).clamp(0.0, 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[test]
+    fn plants_thrive_at_their_optimum_and_not_past_their_limits() {
+        let vegetation = VegetationAttributes::default();
+        assert_eq!(vegetation.warmth(vegetation.optimum_temperature), 1.0);
+        assert_eq!(vegetation.warmth(vegetation.frost_temperature), 0.0);
+        assert_eq!(vegetation.warmth(vegetation.frost_temperature - 10.0), 0.0);
+        assert_eq!(vegetation.warmth(vegetation.heat_temperature), 0.0);
+        assert_eq!(vegetation.warmth(vegetation.heat_temperature + 10.0), 0.0);
+
+        let mild = (vegetation.frost_temperature + vegetation.optimum_temperature) / 2.0;
+        assert!((vegetation.warmth(mild) - 0.5).abs() < 1e-6);
+    }
+
+    /// Biomass left on a fully grown tile at the optimum temperature after an epoch with its
+    /// soil holding `moisture` of the water it can
+    fn biomass_after_an_epoch(moisture: f32) -> f32 {
+        let ecosystem = EcosystemAttributes::from(&Config::defaults());
+        let soil = ecosystem.vegetation.full_cover_soil;
+        let mut elevation = ElevationBundle::from(TileType::Grass, 5.0, 0.0);
+        elevation.soil.value = soil;
+        elevation.water.value = moisture * soil;
+
+        let mut world = World::new();
+        let tile = world
+            .spawn((
+                Biomass { value: 1.0 },
+                elevation,
+                Temperature {
+                    value: ecosystem.vegetation.optimum_temperature,
+                },
+                TileType::Grass,
+                Neighbours { ids: vec![] },
+            ))
+            .id();
+        world.insert_resource(Epochs::default());
+        world.insert_resource(ecosystem);
+        world.run_system_once(vegetation_system);
+        world.get::<Biomass>(tile).unwrap().value
+    }
+
+    #[test]
+    fn drought_dies_plants_back() {
+        let vegetation = VegetationAttributes::default();
+        assert_eq!(biomass_after_an_epoch(1.0), 1.0);
+        assert_eq!(biomass_after_an_epoch(vegetation.drought_moisture), 1.0);
+        let dry = biomass_after_an_epoch(vegetation.drought_moisture / 2.0);
+        assert!((dry - (1.0 - vegetation.dieback_rate)).abs() < 1e-6);
+        let parched = biomass_after_an_epoch(0.0);
+        assert!((parched - (1.0 - vegetation.dieback_rate)).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,195 @@
+use bevy::prelude::*;
+use serde::Serialize;
+use std::fmt;
+
+use crate::components::{
+    Aquifer, ElevationBundle, Humidity, PendingHumidityRedistribution, Snowpack,
+};
+use crate::terrain::TileType;
+use crate::Epochs;
+
+////////////////////////////////////////// Water Budget /////////////////////////////////////////
+
+/// Where the planet's water is and how it moved during the last epoch.
+///
+/// Ocean tiles keep a fixed water level, so the water they gain and lose is kept in a shared
+/// reservoir instead. Everything else is measured from the tiles at the end of every epoch.
+#[derive(Debug, Clone, Default, Serialize, Resource)]
+pub struct WaterBudget {
+    // water the ocean has gained since the world was generated, on top of its tiles' water
+    pub ocean_balance: f32,
+
+    // stores at the end of the last epoch
+    pub ocean: f32,
+    pub atmosphere: f32,
+    // water standing above the soil
+    pub surface: f32,
+    // water soaked into the soil
+    pub ground: f32,
+    // water held in the rock beneath the soil
+    pub aquifer: f32,
+    // water frozen into snow and glaciers
+    pub ice: f32,
+
+    // flows during the last epoch
+    pub evaporation: f32,
+    // soil water plants released into the air
+    pub transpiration: f32,
+    pub precipitation: f32,
+    // water seeping into the aquifers, and rising back out of them
+    pub infiltration: f32,
+    pub spring_discharge: f32,
+    // rain that settled as snow, and snow and ice that melted
+    pub snowfall: f32,
+    pub meltwater: f32,
+    pub runoff_to_ocean: f32,
+    // rain that fell without leaving the air, outside of strict mode
+    pub created: f32,
+    // humidity and overflow sent to tiles that couldn't take it, outside of strict mode
+    pub lost: f32,
+    // change in the total that none of the flows account for, which should stay near 0
+    pub imbalance: f32,
+
+    // total when the epoch started
+    #[serde(skip)]
+    opening_total: f32,
+}
+
+impl WaterBudget {
+    /// Budget for a world whose ocean has gained `ocean_balance` since it was generated
+    pub fn new(ocean_balance: f32) -> Self {
+        WaterBudget {
+            ocean_balance,
+            ..default()
+        }
+    }
+
+    pub fn total(&self) -> f32 {
+        self.ocean + self.atmosphere + self.surface + self.ground + self.aquifer + self.ice
+    }
+
+    /// Water that left an ocean tile for the air
+    pub fn ocean_evaporated(&mut self, amount: f32) {
+        self.ocean_balance -= amount;
+        self.ocean -= amount;
+    }
+
+    /// Water that fell or flowed into an ocean tile
+    pub fn ocean_received(&mut self, amount: f32) {
+        self.ocean_balance += amount;
+        self.ocean += amount;
+    }
+
+    /// Recounts the stores from the tiles
+    fn measure<'a>(
+        &mut self,
+        tiles: impl Iterator<
+            Item = (
+                &'a ElevationBundle,
+                &'a Humidity,
+                &'a PendingHumidityRedistribution,
+                &'a Aquifer,
+                &'a Snowpack,
+                &'a TileType,
+            ),
+        >,
+    ) {
+        self.ocean = self.ocean_balance;
+        self.atmosphere = 0.0;
+        self.surface = 0.0;
+        self.ground = 0.0;
+        self.aquifer = 0.0;
+        self.ice = 0.0;
+        for (elevation, humidity, pending_humidity, aquifer, snowpack, tile_type) in tiles {
+            self.atmosphere += humidity.value + pending_humidity.value + pending_humidity.rain;
+            self.aquifer += aquifer.water;
+            self.ice += snowpack.value + elevation.ice.value;
+            match tile_type {
+                TileType::Ocean => self.ocean += elevation.water.value,
+                _ => {
+                    self.surface += (elevation.water.value - elevation.soil.value).max(0.0);
+                    self.ground += elevation.water.value.min(elevation.soil.value);
+                }
+            }
+        }
+    }
+}
+
+/// Takes the first measurement once the tiles are spawned
+pub fn measure_water_budget(
+    query: Query<(
+        &ElevationBundle,
+        &Humidity,
+        &PendingHumidityRedistribution,
+        &Aquifer,
+        &Snowpack,
+        &TileType,
+    )>,
+    mut budget: ResMut<WaterBudget>,
+) {
+    budget.measure(query.iter());
+}
+
+/// Clears last epoch's flows before any water moves
+pub fn open_water_budget(mut debug: ResMut<Epochs>, mut budget: ResMut<WaterBudget>) {
+    debug.fn_order.push("open_water_budget".to_string());
+    *budget = WaterBudget {
+        ocean_balance: budget.ocean_balance,
+        ocean: budget.ocean,
+        atmosphere: budget.atmosphere,
+        surface: budget.surface,
+        ground: budget.ground,
+        aquifer: budget.aquifer,
+        ice: budget.ice,
+        opening_total: budget.total(),
+        ..default()
+    };
+}
+
+/// Recounts the stores and checks them against the flows once all the water has moved
+pub fn close_water_budget(
+    mut debug: ResMut<Epochs>,
+    query: Query<(
+        &ElevationBundle,
+        &Humidity,
+        &PendingHumidityRedistribution,
+        &Aquifer,
+        &Snowpack,
+        &TileType,
+    )>,
+    mut budget: ResMut<WaterBudget>,
+) {
+    debug.fn_order.push("close_water_budget".to_string());
+    budget.measure(query.iter());
+    budget.imbalance = budget.total() - budget.opening_total - budget.created + budget.lost;
+}
+
+impl fmt::Display for WaterBudget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Water: {:.2} total (ocean {:.2}, atmosphere {:.2}, surface {:.2}, ground {:.2}, \
+             aquifer {:.2}, ice {:.2}), evaporation {:.2}, transpiration {:.2}, \
+             precipitation {:.2}, snowfall {:.2}, meltwater {:.2}, infiltration {:.2}, \
+             springs {:.2}, runoff to ocean {:.2}, created {:.2}, lost {:.2}, imbalance {:.4}",
+            self.total(),
+            self.ocean,
+            self.atmosphere,
+            self.surface,
+            self.ground,
+            self.aquifer,
+            self.ice,
+            self.evaporation,
+            self.transpiration,
+            self.precipitation,
+            self.snowfall,
+            self.meltwater,
+            self.infiltration,
+            self.spring_discharge,
+            self.runoff_to_ocean,
+            self.created,
+            self.lost,
+            self.imbalance
+        )
+    }
+}
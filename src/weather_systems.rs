@@ -1,22 +1,31 @@
 
 
 use bevy::prelude::*;
+use rand::Rng;
 
 
 
 
+use crate::atmosphere::Atmosphere;
+use crate::climate::{relative_insolation, Season};
 use crate::components::{
-    DistancesFromVolcano, ElevationBundle, HexCoordinates, HigherNeighbours, Humidity,
-    IncomingOverflow, LowerNeighbours, Neighbours, PendingHumidityRedistribution,
-    Temperature, TileTypeChanged,
+    Aquifer, Biomass, DebugWeatherBundle, DistancesFromVolcano, ElevationBundle,
+    GeoCoordinates, HexCoordinates, HigherNeighbours,
+    Humidity, IncomingOverflow, Lake, LowerNeighbours, Neighbours, OutgoingOverflow,
+    PendingHumidityRedistribution,
+    Temperature, TileTypeChanged, Wind,
 };
-use crate::terrain::{TileType, WeatherEffects, TileAssets};
-use crate::utils::RandomSelection;
+use crate::grid::Grid;
+use crate::rules::{TileConditions, TransitionRules};
+use crate::sediment::{entrain, mean_drop, transport_capacity, SedimentBudget};
+use crate::terrain::{TileType, TileAssets};
+use crate::utils::{RandomSelection, RngStream, WorldRng};
+use crate::water_budget::WaterBudget;
 use crate::world::{
     EcosystemAttributes, ElevationAttributes, ErosionAttributes, MapAttributes,
     TemperatureAttributes,
 };
-use crate::{pointy_layout, DebugWeatherBundle, Epochs, GameStates};
+use crate::{Epochs, GameStates};
 
 // TODO: move this to a config file
 pub const SIGMOID_STEEPNESS: f32 = 1.0;
@@ -32,26 +41,39 @@ pub fn sigmoid(x: f32) -> f32 {
 pub fn morph_terrain_system(
     mut debug: ResMut<Epochs>,
     mut commands: Commands,
-    mut query: Query<(Entity, &ElevationBundle, &Humidity, &mut TileType)>,
+    mut query: Query<(
+        Entity,
+        &ElevationBundle,
+        &Humidity,
+        &Temperature,
+        &Biomass,
+        &Aquifer,
+        &mut TileType,
+    )>,
     elevation_attributes: Res<ElevationAttributes>,
     ecosystem_attributes: Res<EcosystemAttributes>,
+    rules: Res<TransitionRules>,
+    mut world_rng: ResMut<WorldRng>,
 ) {
     debug.fn_order.push("morph_terrain_system".to_string());
-    for (entity, elevation, humidity, mut tile_type) in query.iter_mut() {
-        // humidity effects
-        let mut tile_probabilities = humidity.apply_weather(&tile_type);
-        tile_probabilities.extend(
-            (
-                &elevation.water,
-                &elevation.soil,
-                &ecosystem_attributes.terrain_change_sensitivity,
-            )
-                .apply_weather(&tile_type),
-        );
-        tile_probabilities
-            .extend((&elevation.bedrock, &*elevation_attributes).apply_weather(&tile_type));
-
-        let new_tile = tile_probabilities.pick_random();
+    let rng = world_rng.stream(RngStream::Morph);
+    let params = rules.params(&elevation_attributes, &ecosystem_attributes);
+    for (entity, elevation, humidity, temperature, biomass, aquifer, mut tile_type) in
+        query.iter_mut()
+    {
+        let conditions = TileConditions {
+            humidity: humidity.value,
+            water: elevation.water.value,
+            soil: elevation.soil.value,
+            elevation: elevation.bedrock.value / elevation_attributes.highest_elevation,
+            temperature: temperature.value,
+            biomass: biomass.value,
+            spring: aquifer.spring,
+            ice: elevation.ice.value,
+        };
+        let tile_probabilities = rules.transitions(*tile_type, &conditions, &params);
+
+        let new_tile = tile_probabilities.pick_random(rng);
         if new_tile != *tile_type {
             *tile_type = new_tile;
             commands.entity(entity).insert(TileTypeChanged);
@@ -72,8 +94,8 @@ pub fn update_terrain_assets(
         &TileTypeChanged,
     )>,
     tile_assets: Res<TileAssets>,
-    mut next_state: ResMut<NextState<GameStates>>,
     map_attributes: Res<MapAttributes>,
+    grid: Res<Grid>,
 ) {
     debug.fn_order.push("update_terrain_assets".to_string());
     for (
@@ -85,18 +107,26 @@ pub fn update_terrain_assets(
         _,
     ) in query.iter_mut()
     {
-        let world_pos = pointy_layout(map_attributes.hex_size).hex_to_world_pos(hex.0);
         // update entity with new mesh, material and transform
         let scene = tile_assets.get_scene_handle(*tile_type).unwrap();
-        *transform =
-            Transform::from_xyz(world_pos.x, 0.0, world_pos.y).with_scale(Vec3::splat(2.0));
+        *transform = grid.tile_transform(hex.0, map_attributes.hex_size);
         *scene_bundle = scene.clone();
         // add back picking components after the update
         commands.entity(entity).remove::<TileTypeChanged>();
     }
+}
 
-    // Finish epoch
-    next_state.set(GameStates::Waiting);
+/// Counts the finished epoch and either starts the next one or waits for input
+pub fn finish_epoch(mut epochs: ResMut<Epochs>, mut next_state: ResMut<NextState<GameStates>>) {
+    epochs.fn_order.push("finish_epoch".to_string());
+    epochs.epochs += 1;
+
+    if epochs.epochs_to_run > 0 {
+        epochs.epochs_to_run -= 1;
+        next_state.set(GameStates::EpochStart);
+    } else {
+        next_state.set(GameStates::Waiting);
+    }
 }
 
 /////////////////////////////////Weather Systems//////////////////////////////////////////////////
@@ -108,13 +138,26 @@ pub fn evaporation_system(
         &mut Humidity,
         &mut DebugWeatherBundle,
         &Temperature,
+        &Biomass,
+        &GeoCoordinates,
         &TileType,
     )>,
     ecosystem: Res<EcosystemAttributes>,
     temperature_attributes: Res<TemperatureAttributes>,
+    season: Res<Season>,
+    mut budget: ResMut<WaterBudget>,
 ) {
     debug.fn_order.push("evaporation_system".to_string());
-    for (mut elevation, mut humidity, mut weather, temperature, tile_type) in query.iter_mut() {
+    for (
+        mut elevation,
+        mut humidity,
+        mut weather,
+        temperature,
+        biomass,
+        geo_coordinates,
+        tile_type,
+    ) in query.iter_mut()
+    {
         // Normalize temperature to be between 0 and 1
         let normalized_temperature = (temperature.value / temperature_attributes.base_temperature)
             .max(0.0)
@@ -126,37 +169,67 @@ pub fn evaporation_system(
             _ => 1.0,
         };
 
+        // More sun in summer dries tiles out faster
+        let sunlight =
+            relative_insolation(&temperature_attributes, &season, geo_coordinates.latitude);
+
         // Calculate evaporation
         weather.evaporation.value = (normalized_temperature
             * elevation.water.value
             * ecosystem.evaporation_factor
-            * tile_factor)
+            * tile_factor
+            * sunlight)
             .max(0.0);
 
+        // Oceans keep their level and draw on the shared reservoir, which can run dry in strict mode
+        if *tile_type == TileType::Ocean && ecosystem.strict_water_conservation {
+            weather.evaporation.value = weather.evaporation.value.min(budget.ocean.max(0.0));
+        }
+
         assert!(weather.evaporation.value >= 0.0);
         humidity.value += weather.evaporation.value;
+        budget.evaporation += weather.evaporation.value;
 
         // Oceans don't lose water to evaporation
         let water_lost_to_evaporation = match tile_type {
-            TileType::Ocean => 0.0,
+            TileType::Ocean => {
+                budget.ocean_evaporated(weather.evaporation.value);
+                0.0
+            }
             _ => weather.evaporation.value,
         };
         elevation.water.value -= water_lost_to_evaporation.max(0.0);
+
+        // Plants draw water from the soil, but not from the surface water above it
+        let soil_water = match tile_type {
+            TileType::Ocean => 0.0,
+            _ => elevation.water.value.min(elevation.soil.value).max(0.0),
+        };
+        weather.transpiration.value = (normalized_temperature
+            * soil_water
+            * biomass.value
+            * ecosystem.vegetation.transpiration_factor
+            * sunlight)
+            .clamp(0.0, soil_water);
+        humidity.value += weather.transpiration.value;
+        budget.transpiration += weather.transpiration.value;
+        elevation.water.value -= weather.transpiration.value;
     }
 }
 
 pub fn precipitation_system(
     mut debug: ResMut<Epochs>,
     mut query: Query<(
-        &Humidity,
+        &mut Humidity,
         &mut DebugWeatherBundle,
         &TileType,
         &mut ElevationBundle,
     )>,
     ecosystem_attributes: Res<EcosystemAttributes>,
+    mut budget: ResMut<WaterBudget>,
 ) {
     debug.fn_order.push("precipitation_system".to_string());
-    for (humidity, mut weather, tile_type, mut water_level) in query.iter_mut() {
+    for (mut humidity, mut weather, tile_type, mut water_level) in query.iter_mut() {
         let tile_factor = match tile_type {
             TileType::Mountain => 0.7,
             TileType::Ocean => 0.5,
@@ -170,9 +243,20 @@ pub fn precipitation_system(
             factor * humidity.value * tile_factor * ecosystem_attributes.precipitation_factor;
 
         weather.precipitation.value = precipitation_increment;
+        budget.precipitation += precipitation_increment;
+
+        // Rain only leaves the air when water is conserved
+        if ecosystem_attributes.strict_water_conservation {
+            humidity.value -= precipitation_increment;
+        } else {
+            budget.created += precipitation_increment;
+        }
 
         water_level.water.value += match tile_type {
-            TileType::Ocean => 0.0,
+            TileType::Ocean => {
+                budget.ocean_received(precipitation_increment);
+                0.0
+            }
             _ => precipitation_increment,
         }
     }
@@ -194,8 +278,7 @@ pub fn calculate_neighbour_heights_system(
         .push("calculate_neighbour_heights_system".to_string());
 
     for (entity, elevation, neighbours) in query.iter_mut() {
-        let this_entity_height =
-            elevation.bedrock.value + elevation.water.value + elevation.soil.value;
+        let this_entity_height = elevation.height();
 
         // Reset the lists of lower and higher neighbours
         let mut lower_neighbours = LowerNeighbours { ids: Vec::new() };
@@ -203,9 +286,7 @@ pub fn calculate_neighbour_heights_system(
 
         for neighbour_id in &neighbours.ids {
             if let Ok(neighbour_elevation) = neighbour_query.get(*neighbour_id) {
-                let neighbour_height = neighbour_elevation.bedrock.value
-                    + neighbour_elevation.soil.value
-                    + neighbour_elevation.water.value;
+                let neighbour_height = neighbour_elevation.height();
 
                 // Include equal level into higher_neighbours
                 if neighbour_height < this_entity_height {
@@ -235,40 +316,85 @@ pub fn calculate_neighbour_heights_system(
 
 pub fn redistribute_humidity_system(
     mut debug: ResMut<Epochs>,
-    _commands: Commands,
     mut query: Query<(
         Entity,
         &mut Humidity,
         &mut DebugWeatherBundle,
-        &TileType,
-        &HigherNeighbours,
+        &Wind,
+        &HexCoordinates,
+        &ElevationBundle,
+        &Neighbours,
     )>,
+    neighbour_query: Query<(&HexCoordinates, &ElevationBundle)>,
     mut incoming_humidity_query: Query<&mut PendingHumidityRedistribution>,
     ecosystem: Res<EcosystemAttributes>,
+    grid: Res<Grid>,
+    mut budget: ResMut<WaterBudget>,
 ) {
     debug
         .fn_order
         .push("redistribute_humidity_system".to_string());
+    let wind_attributes = &ecosystem.wind;
+
+    for (_entity, mut humidity, mut weather, wind, hex_coordinates, elevation, neighbours) in
+        query.iter_mut()
+    {
+        let height = elevation.height();
+
+        // Humidity is carried downwind, split between the neighbours the wind points towards
+        let downwind = Vec2::new(wind.east, wind.north).normalize_or_zero();
+        let targets: Vec<(Entity, f32, f32)> = neighbours
+            .ids
+            .iter()
+            .filter_map(|id| {
+                let (neighbour_hex, neighbour_elevation) = neighbour_query.get(*id).ok()?;
+                let alignment = grid
+                    .heading(hex_coordinates.0, neighbour_hex.0)
+                    .dot(downwind);
+                let neighbour_height = neighbour_elevation.height();
+                (alignment > 0.0).then_some((*id, alignment, neighbour_height - height))
+            })
+            .collect();
+        let total_alignment: f32 = targets.iter().map(|(_, alignment, _)| alignment).sum();
+        if total_alignment <= 0.0 {
+            weather.humidity_sent.value = 0.0;
+            weather.humidity_received.value = 0.0;
+            continue;
+        }
 
-    for (_entity, mut humidity, mut weather, _tile_type, higher_neighbours) in query.iter_mut() {
-        let num_higher_neighbours = higher_neighbours.ids.len() as f32;
+        // Stronger winds carry more of the humidity away
+        let wind_strength = (wind.speed() / wind_attributes.prevailing_wind_speed).min(1.0);
         let factor = sigmoid(SIGMOID_STEEPNESS * (humidity.value - 1.0));
-        let humidity_to_escape = humidity.value * factor * ecosystem.humidity_escape_factor;
+        let humidity_to_escape =
+            humidity.value * factor * ecosystem.humidity_escape_factor * wind_strength;
         assert!(humidity_to_escape >= 0.0);
 
-        for &(neighbour_id, _neighbour_height) in &higher_neighbours.ids {
-            let proportion = 1.0 / num_higher_neighbours;
-            let humidity_for_neighbour = humidity_to_escape * proportion;
+        let mut humidity_undelivered = 0.0;
+        for &(neighbour_id, alignment, rise) in &targets {
+            let humidity_for_neighbour = humidity_to_escape * alignment / total_alignment;
 
-            if humidity_for_neighbour < 0.0 {
-                continue;
-            }
+            // Air pushed up a slope cools and rains out on the windward side, leaving a rain
+            // shadow behind the ridge
+            let rain = humidity_for_neighbour
+                * (wind_attributes.orographic_rain_factor * rise.max(0.0)).min(1.0);
 
-            if let Ok(mut incoming_humidity) = incoming_humidity_query
-                .get_mut(neighbour_id) { incoming_humidity.value += humidity_for_neighbour; }
+            if let Ok(mut incoming_humidity) = incoming_humidity_query.get_mut(neighbour_id) {
+                incoming_humidity.value += humidity_for_neighbour - rain;
+                incoming_humidity.rain += rain;
+            } else {
+                humidity_undelivered += humidity_for_neighbour;
+            }
         }
-        humidity.value = (humidity.value - humidity_to_escape).max(0.0);
-        weather.humidity_sent.value = humidity_to_escape;
+
+        // Humidity that found nowhere to go stays put when water is conserved
+        let humidity_sent = if ecosystem.strict_water_conservation {
+            humidity_to_escape - humidity_undelivered
+        } else {
+            budget.lost += humidity_undelivered;
+            humidity_to_escape
+        };
+        humidity.value = (humidity.value - humidity_sent).max(0.0);
+        weather.humidity_sent.value = humidity_sent;
         weather.humidity_received.value = 0.0;
     }
     debug
@@ -284,17 +410,31 @@ pub fn apply_humidity_redistribution(
         &mut Humidity,
         &mut DebugWeatherBundle,
         &mut PendingHumidityRedistribution,
+        &mut ElevationBundle,
+        &TileType,
     )>,
     mut next_state: ResMut<NextState<GameStates>>,
+    mut budget: ResMut<WaterBudget>,
 ) {
     debug
         .fn_order
         .push("apply_humidity_redistribution".to_string());
-    for (_entity, mut humidity, mut weather, mut redistribution) in query.iter_mut() {
+    for (_entity, mut humidity, mut weather, mut redistribution, mut elevation, tile_type) in
+        query.iter_mut()
+    {
         humidity.value += redistribution.value;
 
         weather.humidity_received.value = redistribution.value;
         redistribution.value = 0.0;
+
+        // Rain from air forced uphill falls on this tile
+        weather.precipitation.value += redistribution.rain;
+        budget.precipitation += redistribution.rain;
+        match tile_type {
+            TileType::Ocean => budget.ocean_received(redistribution.rain),
+            _ => elevation.water.value += redistribution.rain,
+        }
+        redistribution.rain = 0.0;
     }
 
     next_state.set(GameStates::EpochFinish);
@@ -313,21 +453,37 @@ pub fn redistribute_overflow_system(
         &TileType,
         &mut DebugWeatherBundle,
     )>,
-    mut incoming_overflow_query: Query<&mut IncomingOverflow>,
+    mut incoming_overflow_query: Query<(&mut IncomingOverflow, Has<Lake>)>,
     erosion_attributes: Res<ErosionAttributes>,
+    ecosystem: Res<EcosystemAttributes>,
+    mut world_rng: ResMut<WorldRng>,
+    mut budget: ResMut<WaterBudget>,
 ) {
     debug
         .fn_order
         .push("redistribute_overflow_system".to_string());
+    let rng = world_rng.stream(RngStream::Overflow);
 
     // Create a component for each lower neighbour, containing their share of the overflow
     for (entity, mut elevation, lower_neighbours, tiletype, mut weather) in query.iter_mut() {
         // if there is an altitude difference, but no lower neighbours, something is wrong
         assert!(!lower_neighbours.ids.is_empty());
+        // tiles that don't overflow this epoch show none instead of what they sent last time
+        weather.overflow_split.clear();
+        weather.overflow.water = 0.0;
+        weather.overflow.soil = 0.0;
+
+        // Lakes drain over their rim instead
+        if incoming_overflow_query
+            .get(entity)
+            .is_ok_and(|(_, is_lake)| is_lake)
+        {
+            continue;
+        }
 
         let overflow_factor = sigmoid(SIGMOID_STEEPNESS * (elevation.water.value - 1.0));
         // Nothing is lower than oceans so they don't overflow
-        let water_overflow = match tiletype {
+        let mut water_overflow = match tiletype {
             TileType::Ocean => 0.0,
             _ => {
                 (elevation.water.value - elevation.soil.value).max(0.0)
@@ -340,30 +496,51 @@ pub fn redistribute_overflow_system(
             continue;
         }
 
-        // erosion is influenced by soil,water, + general erosion factor
-        let normalized_overflow = weather.overflow.water / 1.0;
-        let normalized_soil = elevation.soil.value / 1.0;
-        let erosion_factor =
-            (normalized_soil * normalized_overflow * erosion_attributes.erosion_factor).min(1.0);
-        // this reduces the bedrock level so it needs to be 0-1 normalized to the current bedrock level
-        let soil_overflow = erosion_factor * elevation.bedrock.value;
-
-        let lowest_neighbour = crate::utils::get_lowest_neighbour(lower_neighbours);
-        assert!(lowest_neighbour != entity);
-
-        incoming_overflow_query
-            .get_mut(lowest_neighbour)
-            .ok()
-            .map(|mut incoming_overflow| {
-                incoming_overflow.water += water_overflow;
-                incoming_overflow.soil += soil_overflow;
-            });
-
-        // TODO: does soil need to be reduced also? this should probably be split amongs bedrock and soil
-        // assert!(overflow.soil <= elevation.soil.value);
-        // elevation.soil.value = (elevation.soil.value - overflow.soil).max(0.0);
-        assert!(elevation.bedrock.value >= soil_overflow);
-        elevation.bedrock.value = (elevation.bedrock.value - soil_overflow).max(0.0);
+        let height = elevation.height();
+        let split = erosion_attributes
+            .flow_routing
+            .split(height, lower_neighbours, rng);
+
+        // the overflow picks up sediment in proportion to its flow and how steeply it falls
+        let slope = mean_drop(height, lower_neighbours, &split);
+        let capacity = transport_capacity(&erosion_attributes, water_overflow, slope);
+        let (soil_eroded, bedrock_eroded) = entrain(&erosion_attributes, &elevation, capacity);
+        let mut soil_overflow = soil_eroded + bedrock_eroded;
+
+        let mut undelivered = 0.0;
+        for (neighbour, share) in split {
+            assert!(neighbour != entity);
+            let outflow = OutgoingOverflow {
+                water: water_overflow * share,
+                soil: soil_overflow * share,
+            };
+            match incoming_overflow_query.get_mut(neighbour) {
+                Ok((mut incoming_overflow, _)) => {
+                    incoming_overflow.water += outflow.water;
+                    incoming_overflow.soil += outflow.soil;
+                    weather.overflow_split.push((neighbour, outflow));
+                }
+                Err(_) => undelivered += share,
+            }
+        }
+
+        // Overflow that found nowhere to go stays put when water is conserved
+        if undelivered > 0.0 {
+            if ecosystem.strict_water_conservation {
+                if weather.overflow_split.is_empty() {
+                    continue;
+                }
+                water_overflow *= 1.0 - undelivered;
+            } else {
+                budget.lost += water_overflow * undelivered;
+            }
+        }
+
+        // sediment is only picked up where there is somewhere to take it
+        soil_overflow *= 1.0 - undelivered;
+        elevation.soil.value = (elevation.soil.value - soil_eroded * (1.0 - undelivered)).max(0.0);
+        elevation.bedrock.value =
+            (elevation.bedrock.value - bedrock_eroded * (1.0 - undelivered)).max(0.0);
         elevation.water.value = (elevation.water.value - water_overflow).max(0.0);
 
         weather.overflow.water = water_overflow;
@@ -381,6 +558,8 @@ pub fn apply_water_overflow(
         &TileType,
         &mut DebugWeatherBundle,
     )>,
+    mut budget: ResMut<WaterBudget>,
+    mut sediment_budget: ResMut<SedimentBudget>,
 ) {
     debug.fn_order.push("apply_water_overflow".to_string());
 
@@ -391,14 +570,25 @@ pub fn apply_water_overflow(
         }
 
         elevation.water.value += match tile_type {
-            TileType::Ocean => 0.0,
+            TileType::Ocean => {
+                budget.runoff_to_ocean += incoming_overflow.water;
+                budget.ocean_received(incoming_overflow.water);
+                0.0
+            }
             _ => incoming_overflow.water,
         };
 
-        elevation.soil.value += match tile_type {
-            TileType::Ocean => 0.0,
-            _ => incoming_overflow.soil,
-        };
+        // sediment settles where it arrives, and is picked up again if the water can carry it on
+        elevation.soil.value += incoming_overflow.soil;
+        sediment_budget.transported += incoming_overflow.soil;
+        if *tile_type == TileType::Ocean {
+            // it builds up the sea floor and pushes the water above it out into the rest of the
+            // ocean, so the sea stays level
+            let displaced = incoming_overflow.soil.min(elevation.water.value);
+            elevation.water.value -= displaced;
+            budget.ocean_balance += displaced;
+            sediment_budget.deposited_at_sea += incoming_overflow.soil;
+        }
 
         weather.overflow_received.soil = incoming_overflow.soil;
         weather.overflow_received.water = incoming_overflow.water;
@@ -418,15 +608,24 @@ pub fn apply_vulcanism(
         &DistancesFromVolcano,
     )>,
     elevation_attributes: Res<ElevationAttributes>,
+    mut world_rng: ResMut<WorldRng>,
+    mut sediment_budget: ResMut<SedimentBudget>,
+    temperature_attributes: Res<TemperatureAttributes>,
+    mut atmosphere: ResMut<Atmosphere>,
 ) {
     debug.fn_order.push("apply_vulcanism".to_string());
+    let rng = world_rng.stream(RngStream::Vulcanism);
 
     for (_entity, mut elevation, _weather, _tile_type, distance_from_volcanos) in query.iter_mut()
     {
         for distance in &distance_from_volcanos.0 {
             let probability = 1.0 - (*distance as f32 / elevation_attributes.mountain_spread);
-            if rand::random::<f32>() < probability {
+            if rng.gen::<f32>() < probability {
                 elevation.bedrock.value += elevation_attributes.epoch_increment;
+                sediment_budget.uplift += elevation_attributes.epoch_increment;
+                // eruptions release the gas trapped in the magma
+                atmosphere.co2 += temperature_attributes.atmosphere.volcanic_outgassing
+                    * elevation_attributes.epoch_increment;
             }
         }
     }
@@ -1,48 +1,98 @@
 use bevy::prelude::*;
 
-use crate::terrain::TileType;
-use crate::utils::RandomSelection;
-use serde::{Deserialize};
+use crate::aquifer::AquiferAttributes;
+use crate::atmosphere::AtmosphereAttributes;
+use crate::biomes::BiomeTable;
+use crate::climate::Orbit;
+use crate::config::{Config, ConfigError};
+use crate::glaciers::GlacierAttributes;
+use crate::grid::MapShape;
+use crate::hydrology::FlowRouting;
+use crate::map_generation::TerrainGeneratorConfig;
+use crate::rules::{RulesError, TransitionRules};
+use crate::vegetation::VegetationAttributes;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
 
-#[derive(Debug, Deserialize)]
+/// Everything a world is run with, kept in its saves.
+///
+/// Parts marked `#[serde(default)]` can be left out of a save and start from their defaults.
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
 pub struct WorldAttributes {
     pub erosion: ErosionAttributes,
     pub elevation: ElevationAttributes,
     pub temperature: TemperatureAttributes,
     pub map: MapAttributes,
     pub ecosystem: EcosystemAttributes,
+    #[serde(default)]
+    pub biomes: BiomeTable,
+    #[serde(default)]
+    pub rules: TransitionRules,
 }
 
 impl WorldAttributes {
     pub fn load() -> Self {
-        let config_str = include_str!("../defaults.json");
-        let config: Config = serde_json::from_str(config_str).unwrap();
+        Self::from(&Config::defaults())
+    }
+
+    /// Loads a config file layered over the built-in defaults
+    pub fn from_path(path: &Path) -> Result<Self, ConfigError> {
+        Ok(Self::from(&Config::from_path(path)?))
+    }
+
+    /// Replaces the built-in terrain transition rules with the ones in a rules file
+    pub fn with_rules(self, path: &Path) -> Result<Self, RulesError> {
+        Ok(Self {
+            rules: TransitionRules::from_path(path)?,
+            ..self
+        })
+    }
+}
+
+impl From<&Config> for WorldAttributes {
+    fn from(config: &Config) -> Self {
         Self {
-            erosion: ErosionAttributes::from(&config),
-            elevation: ElevationAttributes::from(&config),
-            temperature: TemperatureAttributes::from(&config),
-            map: MapAttributes::from(&config),
-            ecosystem: EcosystemAttributes::from(&config),
+            erosion: ErosionAttributes::from(config),
+            elevation: ElevationAttributes::from(config),
+            temperature: TemperatureAttributes::from(config),
+            map: MapAttributes::from(config),
+            ecosystem: EcosystemAttributes::from(config),
+            biomes: config.biomes.clone(),
+            rules: TransitionRules::default(),
         }
     }
 }
 
-#[derive(Debug, serde::Deserialize, Resource)]
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
 pub struct ErosionAttributes {
+    // sediment carried per unit of overflow per unit of height it drops
     pub erosion_factor: f32,
+    // share of the capacity left after taking the soil that goes into wearing down bedrock
+    pub bedrock_erodibility: f32,
     pub overflow_factor: f32,
+    #[serde(default)]
+    pub flow_routing: FlowRouting,
+    #[serde(default = "default_river_threshold")]
+    pub river_threshold: f32,
+}
+
+fn default_river_threshold() -> f32 {
+    0.02
 }
 
 impl From<&Config> for ErosionAttributes {
     fn from(config: &Config) -> Self {
         Self {
             erosion_factor: config.erosion_factor,
+            bedrock_erodibility: config.bedrock_erodibility,
             overflow_factor: config.overflow_factor,
+            flow_routing: config.flow_routing,
+            river_threshold: config.river_threshold,
         }
     }
 }
 
-#[derive(Debug, serde::Deserialize, Resource)]
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
 pub struct ElevationAttributes {
     pub highest_elevation: f32,
     pub vulcanism: f32,
@@ -53,6 +103,8 @@ pub struct ElevationAttributes {
     pub hill_point: f32,
     pub sea_level: f32,
     pub soil_and_water_height_display_factor: f32,
+    #[serde(default)]
+    pub terrain_generator: TerrainGeneratorConfig,
 }
 
 impl From<&Config> for ElevationAttributes {
@@ -60,22 +112,37 @@ impl From<&Config> for ElevationAttributes {
         Self {
             highest_elevation: config.highest_elevation,
             vulcanism: config.vulcanism,
-            mountain_spread: config.mountain_spread * config.map_radius as f32,
+            mountain_spread: config.mountain_spread * config.map_shape.radius(config.map_radius),
             elevation_increment: config.elevation_increment,
             epoch_increment: config.epoch_increment,
             mountain_point: config.mountain_point,
             hill_point: config.hill_point,
             sea_level: config.sea_level,
             soil_and_water_height_display_factor: config.soil_and_water_height_display_factor,
+            terrain_generator: config.terrain_generator.clone(),
         }
     }
 }
 
-#[derive(Debug, serde::Deserialize, Resource)]
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
 pub struct TemperatureAttributes {
     pub base_temperature: f32,
     pub latitude_temperature_variation: f32,
     pub altitude_temperature_variation: f32,
+    #[serde(default)]
+    pub energy_balance: EnergyBalanceAttributes,
+    #[serde(default = "no_seasons")]
+    pub orbit: Orbit,
+    #[serde(default)]
+    pub atmosphere: AtmosphereAttributes,
+}
+
+fn no_seasons() -> Orbit {
+    Orbit {
+        axial_tilt: 0.0,
+        year_length: 1,
+        eccentricity: 0.0,
+    }
 }
 
 impl From<&Config> for TemperatureAttributes {
@@ -84,14 +151,50 @@ impl From<&Config> for TemperatureAttributes {
             base_temperature: config.base_temperature,
             latitude_temperature_variation: config.latitude_temperature_variation,
             altitude_temperature_variation: config.altitude_temperature_variation,
+            energy_balance: EnergyBalanceAttributes::from(config),
+            orbit: config.orbit.clone(),
+            atmosphere: config.atmosphere.clone(),
         }
     }
 }
 
-#[derive(Debug, serde::Deserialize, Resource)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnergyBalanceAttributes {
+    pub solar_constant: f32,
+    pub insolation_variation: f32,
+    pub outgoing_radiation_base: f32,
+    pub outgoing_radiation_slope: f32,
+    pub heat_diffusion: f32,
+    pub ocean_heat_capacity: f32,
+    pub temperature_response: f32,
+}
+
+impl From<&Config> for EnergyBalanceAttributes {
+    fn from(config: &Config) -> Self {
+        Self {
+            solar_constant: config.solar_constant,
+            insolation_variation: config.insolation_variation,
+            outgoing_radiation_base: config.outgoing_radiation_base,
+            outgoing_radiation_slope: config.outgoing_radiation_slope,
+            heat_diffusion: config.heat_diffusion,
+            ocean_heat_capacity: config.ocean_heat_capacity,
+            temperature_response: config.temperature_response,
+        }
+    }
+}
+
+impl Default for EnergyBalanceAttributes {
+    fn default() -> Self {
+        Self::from(&Config::defaults())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
 pub struct MapAttributes {
     pub hex_size: f32,
     pub map_radius: u16,
+    #[serde(default)]
+    pub map_shape: MapShape,
 }
 
 impl From<&Config> for MapAttributes {
@@ -99,16 +202,27 @@ impl From<&Config> for MapAttributes {
         Self {
             hex_size: config.hex_size,
             map_radius: config.map_radius,
+            map_shape: config.map_shape.clone(),
         }
     }
 }
 
-#[derive(Debug, serde::Deserialize, Resource)]
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
 pub struct EcosystemAttributes {
     pub precipitation_factor: f32,
     pub evaporation_factor: f32,
     pub terrain_change_sensitivity: f32,
     pub humidity_escape_factor: f32,
+    #[serde(default)]
+    pub strict_water_conservation: bool,
+    #[serde(default)]
+    pub wind: WindAttributes,
+    #[serde(default)]
+    pub vegetation: VegetationAttributes,
+    #[serde(default)]
+    pub aquifer: AquiferAttributes,
+    #[serde(default)]
+    pub glaciers: GlacierAttributes,
 }
 
 impl From<&Config> for EcosystemAttributes {
@@ -118,86 +232,36 @@ impl From<&Config> for EcosystemAttributes {
             evaporation_factor: config.evaporation_factor,
             terrain_change_sensitivity: config.terrain_change_sensitivity,
             humidity_escape_factor: config.humidity_escape_factor,
+            strict_water_conservation: config.strict_water_conservation,
+            wind: WindAttributes::from(config),
+            vegetation: config.vegetation.clone(),
+            aquifer: config.aquifer.clone(),
+            glaciers: config.glaciers.clone(),
         }
     }
 }
 
-#[derive(Debug, serde::Deserialize, Resource)]
-pub struct Config {
-    hex_size: f32,
-    map_radius: u16,
-    erosion_factor: f32,
-    precipitation_factor: f32,
-    evaporation_factor: f32,
-    highest_elevation: f32,
-    vulcanism: f32,
-    mountain_spread: f32,
-    elevation_increment: f32,
-    epoch_increment: f32,
-    sea_level: f32,
-    terrain_change_sensitivity: f32,
-    mountain_point: f32,
-    hill_point: f32,
-    soil_and_water_height_display_factor: f32,
-    base_temperature: f32,
-    latitude_temperature_variation: f32,
-    altitude_temperature_variation: f32,
-    overflow_factor: f32,
-    humidity_escape_factor: f32,
-}
-
-///////////////////////////////////////// TileGeneration ////////////////////////////////////////////////
-
-// TODO: this doesn't need to be a trait
-pub trait TileTypeGenerator {
-    fn spawn_tile(&self, latitude: f32, altitude: f32, temperature: f32) -> TileType;
-}
-
-impl TileTypeGenerator for WorldAttributes {
-    // TODO: add more tile types and refactor this
-    fn spawn_tile(&self, _latitude: f32, altitude: f32, temperature: f32) -> TileType {
-        let cool_tiles: Vec<(TileType, f32)> = vec![
-            (TileType::Grass, 0.5),
-            (TileType::Forest, 0.5),
-            (TileType::Water, 0.2),
-            (TileType::Dirt, 0.1),
-            (TileType::Hills, 0.2),
-            (TileType::Rocky, 0.1),
-            // (TileType::Swamp, 0.1),
-            (TileType::Jungle, 0.1),
-        ];
-
-        let hot_tiles: Vec<(TileType, f32)> = vec![
-            (TileType::Jungle, 0.7),
-            (TileType::Desert, 0.3),
-            (TileType::Forest, 0.2),
-            // (TileType::Swamp, 0.3),
-            (TileType::Grass, 0.2),
-            (TileType::Water, 0.2),
-            (TileType::Dirt, 0.1),
-            (TileType::Rocky, 0.1),
-        ];
-
-        match temperature {
-            t if t <= 0.0 => TileType::Ice,
-            _ => match altitude {
-                a if a <= self.elevation.sea_level => TileType::Ocean,
-                a if a >= self.elevation.highest_elevation * self.elevation.mountain_point => {
-                    TileType::Mountain
-                }
-                a if a >= self.elevation.highest_elevation * self.elevation.hill_point => {
-                    vec![TileType::Hills, TileType::Rocky].pick_random()
-                }
-                _ => {
-                    if temperature <= self.temperature.base_temperature * 0.85 {
-                        cool_tiles.pick_random()
-                    } else {
-                        hot_tiles.pick_random()
-                    }
-                }
-            },
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindAttributes {
+    pub prevailing_wind_speed: f32,
+    pub thermal_wind_factor: f32,
+    pub orographic_rain_factor: f32,
+}
+
+impl From<&Config> for WindAttributes {
+    fn from(config: &Config) -> Self {
+        Self {
+            prevailing_wind_speed: config.prevailing_wind_speed,
+            thermal_wind_factor: config.thermal_wind_factor,
+            orographic_rain_factor: config.orographic_rain_factor,
         }
     }
 }
 
+impl Default for WindAttributes {
+    fn default() -> Self {
+        Self::from(&Config::defaults())
+    }
+}
+
 ///////////////////////////////////////// Randomness ////////////////////////////////////////////////
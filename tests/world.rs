@@ -0,0 +1,91 @@
+use terraflow::config::Config;
+use terraflow::save::SaveFile;
+use terraflow::world::WorldAttributes;
+use terraflow::World;
+
+/// A world small enough to run a few epochs of quickly
+fn small_world(seed: u64) -> World {
+    let config = Config::from_json(r#"{ "map_radius": 8 }"#).unwrap();
+    World::new(WorldAttributes::from(&config), seed)
+}
+
+/// Every tile and resource a save keeps, as text that can be compared exactly
+fn snapshot(world: &mut World) -> String {
+    serde_json::to_string(&world.save()).unwrap()
+}
+
+#[test]
+fn same_seed_gives_the_same_world() {
+    let mut first = small_world(7);
+    let mut second = small_world(7);
+    assert_eq!(snapshot(&mut first), snapshot(&mut second));
+
+    first.run(10);
+    second.run(10);
+    assert_eq!(first.epochs(), 10);
+    assert_eq!(snapshot(&mut first), snapshot(&mut second));
+}
+
+#[test]
+fn different_seeds_give_different_worlds() {
+    let mut first = small_world(7);
+    let mut second = small_world(8);
+    first.run(10);
+    second.run(10);
+    assert_ne!(snapshot(&mut first), snapshot(&mut second));
+}
+
+#[test]
+fn loaded_world_continues_as_if_never_saved() {
+    let mut uninterrupted = small_world(7);
+    uninterrupted.run(10);
+
+    let mut saved = small_world(7);
+    saved.run(4);
+    let path = std::env::temp_dir().join(format!("terraflow_test_{}.json", std::process::id()));
+    saved.save().write(&path).unwrap();
+    let save = SaveFile::read(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    let mut loaded = World::from_save(save.unwrap());
+    assert_eq!(loaded.epochs(), 4);
+    loaded.run(6);
+    assert_eq!(snapshot(&mut loaded), snapshot(&mut uninterrupted));
+}
+
+#[test]
+fn strict_world_neither_creates_nor_loses_water() {
+    let config =
+        Config::from_json(r#"{ "map_radius": 8, "strict_water_conservation": true }"#).unwrap();
+    let mut world = World::new(WorldAttributes::from(&config), 7);
+    for _ in 0..20 {
+        world.step();
+        let budget = world.statistics().water_budget;
+        assert_eq!(budget.created, 0.0, "epoch {}", world.epochs());
+        assert_eq!(budget.lost, 0.0, "epoch {}", world.epochs());
+        assert!(
+            budget.imbalance.abs() < 1e-3,
+            "epoch {} is off by {}",
+            world.epochs(),
+            budget.imbalance
+        );
+    }
+}
+
+#[test]
+fn erosion_only_moves_rock_and_soil_around() {
+    let mut world = small_world(7);
+    let mut transported = 0.0;
+    for _ in 0..20 {
+        world.step();
+        let budget = world.statistics().sediment_budget;
+        transported += budget.transported;
+        assert!(
+            budget.imbalance.abs() < 1e-2,
+            "epoch {} is off by {}",
+            world.epochs(),
+            budget.imbalance
+        );
+    }
+    assert!(transported > 0.0);
+}